@@ -1,8 +1,8 @@
 use crate::{
     consts,
-    diem::Diem,
+    diem::{AccountSnapshot, Diem},
     error::ApiError,
-    filters::{handle, with_options},
+    filters::{handle_with_diem, with_diem, with_options},
     options::Options,
     types::{AccountBalanceRequest, AccountBalanceResponse, Amount, BlockIdentifier, Currency},
 };
@@ -11,18 +11,21 @@ use warp::Filter;
 
 pub fn routes(
     options: Options,
+    diem: Diem,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::post().and(
         warp::path!("account" / "balance")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(account_balance)),
+            .and(with_diem(diem.clone()))
+            .and_then(handle_with_diem("/account/balance", account_balance)),
     )
 }
 
 async fn account_balance(
     account_balance_request: AccountBalanceRequest,
     options: Options,
+    diem: Diem,
 ) -> Result<AccountBalanceResponse, ApiError> {
     debug!("/account/balance");
 
@@ -38,23 +41,20 @@ async fn account_balance(
         return Err(ApiError::HistoricBalancesUnsupported);
     }
 
-    let diem = Diem::new(&options.diem_endpoint);
-
     let address = account_balance_request.account_identifier.address;
 
-    let (account, metadata) = diem.get_account_with_metadata(&address).await?;
-
-    if account.is_none() {
-        return Err(ApiError::AccountNotFound);
-    }
-
-    let account = account.unwrap();
+    // NOTE: the balances must be the ones at the returned block, since
+    // reconciliation compares them with the operations up to that block
+    let AccountSnapshot {
+        account,
+        latest_transaction: tx,
+    } = diem.get_account_snapshot(&address).await?;
 
-    let tx = diem.get_transactions(metadata.version, 1, false).await?;
+    let account = account.ok_or(ApiError::AccountNotFound)?;
 
     let block_identifier = BlockIdentifier {
-        index: tx[0].version,
-        hash: tx[0].hash.clone().to_string(),
+        index: tx.version,
+        hash: tx.hash.clone().to_string(),
     };
 
     let balances = account
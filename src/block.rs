@@ -1,30 +1,51 @@
 use crate::{
+    cache::BlockCache,
     consts,
     diem::{self, Diem},
     error::ApiError,
-    filters::{handle, with_options},
+    filters::{handle_with_blocks, with_block_cache, with_block_store, with_diem, with_options},
+    metrics,
+    operations::{describe_script, Intent},
     options::Options,
+    prefetch,
+    store::BlockStore,
     types::{
         AccountIdentifier, Amount, Block, BlockIdentifier, BlockRequest, BlockResponse, Currency,
-        Operation, OperationIdentifier, Transaction, TransactionIdentifier,
+        Operation, OperationIdentifier, Transaction, TransactionIdentifier, TransactionMetadata,
     },
 };
-use diem_json_rpc_client::views::{AmountView, EventDataView, TransactionDataView};
+use diem_json_rpc_client::views::{
+    AmountView, BytesView, EventDataView, TransactionDataView, TransactionView,
+};
+use diem_types::transaction::Script;
 use log::debug;
+use std::sync::Arc;
 use warp::Filter;
 
 pub fn routes(
     options: Options,
+    diem: Diem,
+    cache: Arc<BlockCache>,
+    store: Option<Arc<BlockStore>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::post().and(
         warp::path!("block")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(block)),
+            .and(with_diem(diem.clone()))
+            .and(with_block_cache(cache.clone()))
+            .and(with_block_store(store.clone()))
+            .and_then(handle_with_blocks("/block", block)),
     )
 }
 
-async fn block(block_request: BlockRequest, options: Options) -> Result<BlockResponse, ApiError> {
+async fn block(
+    block_request: BlockRequest,
+    options: Options,
+    diem: Diem,
+    cache: Arc<BlockCache>,
+    store: Option<Arc<BlockStore>>,
+) -> Result<BlockResponse, ApiError> {
     debug!("/block");
 
     let network_identifier = block_request.network_identifier;
@@ -34,41 +55,90 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
         return Err(ApiError::BadNetwork);
     }
 
-    let diem = Diem::new(&options.diem_endpoint);
+    let block_identifier = block_request.block_identifier;
+    let block_version = match (block_identifier.index, block_identifier.hash) {
+        (Some(index), _) => index,
+        // NOTE: blocks can only be looked up by hash once they are in the store
+        (None, Some(hash)) => {
+            return store
+                .and_then(|store| store.get_by_hash(&hash))
+                .ok_or(ApiError::BadBlockRequest);
+        }
+        (None, None) => return Err(ApiError::BadBlockRequest),
+    };
 
-    let block_version = block_request
-        .block_identifier
-        .index
-        .ok_or_else(|| ApiError::BadBlockRequest)?;
+    // NOTE: only committed versions can be fetched, so fetched blocks never change
+    prefetch::on_block_request(block_version, &options, &diem, &cache, &store);
+    cache
+        .get_or_fetch(block_version, || {
+            read_block(&diem, store.as_deref(), block_version)
+        })
+        .await
+}
 
-    let metadata = diem.get_metadata(Some(block_version)).await?;
-    let tx = if block_version == 0 {
+/// Reads a block through the on-disk store, if there is one
+async fn read_block(
+    diem: &Diem,
+    store: Option<&BlockStore>,
+    block_version: u64,
+) -> Result<BlockResponse, ApiError> {
+    if let Some(store) = store {
+        let stored = store.get(block_version);
+        metrics::record_cache_lookup("disk", stored.is_some());
+        if let Some(block) = stored {
+            return Ok(block);
+        }
+    }
+
+    let block = fetch_block(diem, block_version).await?;
+    if let Some(store) = store {
+        store.insert(block_version, &block);
+    }
+
+    Ok(block)
+}
+
+async fn fetch_block(diem: &Diem, block_version: u64) -> Result<BlockResponse, ApiError> {
+    let (metadata, tx) = if block_version == 0 {
         // For the genesis block, we populate parent_block_identifier with the
         // same genesis block. Refer to
         // https://www.rosetta-api.org/docs/common_mistakes.html#malformed-genesis-block
-        let one_tx = diem.get_transactions(block_version, 1, true).await?;
-        vec![one_tx[0].clone(), one_tx[0].clone()]
+        let (metadata, one_tx) = diem
+            .get_metadata_with_transactions(Some(block_version), block_version, 1, true)
+            .await?;
+        (metadata, vec![one_tx[0].clone(), one_tx[0].clone()])
     } else {
-        diem.get_transactions(block_version - 1, 2, true).await?
+        diem.get_metadata_with_transactions(Some(block_version), block_version - 1, 2, true)
+            .await?
     };
 
+    Ok(convert_block(metadata.timestamp, &tx[0], &tx[1]))
+}
+
+/// Converts a transaction into a block, given its parent and the ledger
+/// timestamp at its version
+pub fn convert_block(
+    timestamp_usecs: u64,
+    parent: &TransactionView,
+    tx: &TransactionView,
+) -> BlockResponse {
     let block_identifier = BlockIdentifier {
-        index: tx[1].version,
-        hash: tx[1].hash.clone().to_string(),
+        index: tx.version,
+        hash: tx.hash.clone().to_string(),
     };
 
     let parent_block_identifier = BlockIdentifier {
-        index: tx[0].version,
-        hash: tx[0].hash.clone().to_string(),
+        index: parent.version,
+        hash: parent.hash.clone().to_string(),
     };
 
     // block timestamp is in usecs, and Rosetta wants millis
     // Note that this timestamp is 0 for genesis block and any following timeout blocks
-    let timestamp = metadata.timestamp / 1000;
+    let timestamp = timestamp_usecs / 1000;
 
-    let status = diem::vmstatus_to_str(&tx[1].vm_status);
+    let status = diem::vmstatus_to_str(&tx.vm_status);
 
-    let mut operations = tx[1]
+    let mut operations = tx
         .events
         .iter()
         .filter(|event| {
@@ -158,10 +228,48 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
                 status,
                 account,
                 amount,
+                metadata: None,
             }
         })
         .collect::<Vec<_>>();
 
+    // Handle scripts
+
+    // Some scripts, like key rotation and adding a currency, change account
+    // state without emitting any events, so we decode the script to represent
+    // them. We also expose the decoded script as transaction metadata.
+    let mut metadata = None;
+    if let TransactionDataView::UserTransaction {
+        sender,
+        gas_currency,
+        script_bytes,
+        ..
+    } = &tx.transaction
+    {
+        let script = decode_script(script_bytes);
+        metadata = script
+            .as_ref()
+            .and_then(describe_script)
+            .map(|script| TransactionMetadata {
+                script: Some(script),
+            });
+
+        let intent = script.and_then(|script| {
+            let sender = sender.to_string().parse().ok()?;
+            Intent::from_script(sender, gas_currency, &script).ok()
+        });
+
+        if let Some(intent @ Intent::RotateAuthKey(_)) | Some(intent @ Intent::AddCurrency(_)) =
+            intent
+        {
+            for mut operation in intent.into_operations() {
+                operation.operation_identifier.index = operations.len() as u64;
+                operation.status = Some(status.to_string());
+                operations.push(operation);
+            }
+        }
+    }
+
     // Handle transcation fees
 
     // There are no events for transaction fees, since gas is used regardless
@@ -173,10 +281,10 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
         gas_unit_price,
         gas_currency,
         ..
-    } = &tx[1].transaction
+    } = &tx.transaction
     {
         if *gas_unit_price > 0 {
-            let value = gas_unit_price * tx[1].gas_used;
+            let value = gas_unit_price * tx.gas_used;
 
             let currency = Currency {
                 symbol: gas_currency.clone(),
@@ -187,7 +295,7 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
 
             let sent_fee_op = Operation {
                 operation_identifier: OperationIdentifier {
-                    index: tx[1].events.len() as u64,
+                    index: operations.len() as u64,
                     network_index: None,
                 },
                 related_operations: None,
@@ -201,6 +309,7 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
                     value: format!("-{}", value),
                     currency: currency.clone(),
                 }),
+                metadata: None,
             };
 
             operations.push(sent_fee_op);
@@ -209,9 +318,10 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
 
     let transactions = vec![Transaction {
         transaction_identifier: TransactionIdentifier {
-            hash: tx[1].hash.clone().to_string(),
+            hash: tx.hash.clone().to_string(),
         },
         operations,
+        metadata,
     }];
 
     let block = Block {
@@ -221,7 +331,11 @@ async fn block(block_request: BlockRequest, options: Options) -> Result<BlockRes
         transactions,
     };
 
-    let response = BlockResponse { block };
+    BlockResponse { block }
+}
 
-    Ok(response)
+/// The views hold the BCS bytes of the script, or nothing for other payloads
+fn decode_script(script_bytes: &BytesView) -> Option<Script> {
+    let bytes = hex::decode(&script_bytes.0).ok()?;
+    bcs::from_bytes(&bytes).ok()
 }
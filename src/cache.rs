@@ -0,0 +1,167 @@
+use crate::{error::ApiError, metrics, types::BlockResponse};
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+/// Converted blocks, by version. Committed blocks never change, so entries
+/// are only evicted to stay within the size limits.
+pub struct BlockCache {
+    max_blocks: usize,
+    max_bytes: usize,
+    blocks: Mutex<Blocks>,
+    /// Held while a block is fetched, so that concurrent requests for the
+    /// same block wait for it instead of fetching it again
+    fetches: Mutex<HashMap<u64, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+struct Blocks {
+    /// Blocks along with their approximate size in bytes
+    entries: LruCache<u64, (BlockResponse, usize)>,
+    bytes: usize,
+}
+
+impl BlockCache {
+    pub fn new(max_blocks: usize, max_bytes: usize) -> BlockCache {
+        BlockCache {
+            max_blocks,
+            max_bytes,
+            blocks: Mutex::new(Blocks {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            fetches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, version: u64) -> Option<BlockResponse> {
+        let mut blocks = self.blocks.lock().unwrap();
+        blocks.entries.get(&version).map(|(block, _)| block.clone())
+    }
+
+    pub fn insert(&self, version: u64, block: BlockResponse) {
+        // the serialized size is close enough to the memory used by a block
+        let size = match serde_json::to_vec(&block) {
+            Ok(bytes) => bytes.len(),
+            Err(_) => return,
+        };
+        if self.max_blocks == 0 || size > self.max_bytes {
+            return;
+        }
+
+        let mut blocks = self.blocks.lock().unwrap();
+        if let Some((_, replaced_size)) = blocks.entries.put(version, (block, size)) {
+            blocks.bytes -= replaced_size;
+        }
+        blocks.bytes += size;
+
+        while blocks.entries.len() > self.max_blocks || blocks.bytes > self.max_bytes {
+            match blocks.entries.pop_lru() {
+                Some((_, (_, evicted_size))) => blocks.bytes -= evicted_size,
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the cached block, or fetches and caches it. Only one fetch
+    /// runs at a time for a given version.
+    pub async fn get_or_fetch<F, R>(
+        &self,
+        version: u64,
+        fetch_block: F,
+    ) -> Result<BlockResponse, ApiError>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = Result<BlockResponse, ApiError>>,
+    {
+        if self.max_blocks == 0 {
+            return fetch_block().await;
+        }
+        let cached = self.get(version);
+        metrics::record_cache_lookup("memory", cached.is_some());
+        if let Some(block) = cached {
+            return Ok(block);
+        }
+
+        let fetch = self.register_fetch(version);
+        let _fetching = fetch.lock.lock().await;
+
+        // the block is there if another request fetched it while we waited
+        match self.get(version) {
+            Some(block) => Ok(block),
+            None => {
+                let result = fetch_block().await;
+                if let Ok(block) = &result {
+                    self.insert(version, block.clone());
+                }
+                result
+            }
+        }
+    }
+
+    /// Fetches the blocks of a range of versions at once, and caches them.
+    /// Requests for these versions wait for the fetch instead of fetching
+    /// the blocks again, except for versions that were already being fetched.
+    pub async fn fetch_range<F, R>(
+        &self,
+        versions: Range<u64>,
+        fetch_blocks: F,
+    ) -> Result<(), ApiError>
+    where
+        F: FnOnce() -> R,
+        R: Future<Output = Result<Vec<(u64, BlockResponse)>, ApiError>>,
+    {
+        let fetches: Vec<_> = versions
+            .filter(|version| !self.blocks.lock().unwrap().entries.contains(version))
+            .map(|version| self.register_fetch(version))
+            .collect();
+        let _fetching: Vec<_> = fetches
+            .iter()
+            .filter_map(|fetch| fetch.lock.try_lock().ok())
+            .collect();
+
+        for (version, block) in fetch_blocks().await? {
+            self.insert(version, block);
+        }
+        Ok(())
+    }
+
+    /// Shares the lock of the fetch of a version. The lock is forgotten once
+    /// the fetch is over, even if the request is dropped while waiting.
+    fn register_fetch(&self, version: u64) -> Fetch<'_> {
+        let lock = self
+            .fetches
+            .lock()
+            .unwrap()
+            .entry(version)
+            .or_default()
+            .clone();
+        Fetch {
+            fetches: &self.fetches,
+            version,
+            lock,
+        }
+    }
+}
+
+/// Forgets the lock of a fetch when dropped, unless it was replaced by a
+/// later fetch of the same version
+struct Fetch<'a> {
+    fetches: &'a Mutex<HashMap<u64, Arc<tokio::sync::Mutex<()>>>>,
+    version: u64,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for Fetch<'_> {
+    fn drop(&mut self) {
+        let mut fetches = self.fetches.lock().unwrap();
+        if let Some(lock) = fetches.get(&self.version) {
+            if Arc::ptr_eq(lock, &self.lock) {
+                fetches.remove(&self.version);
+            }
+        }
+    }
+}
@@ -1,29 +1,31 @@
 use crate::{
     consts,
-    diem::Diem,
+    diem::{self, Diem},
     error::ApiError,
-    filters::{handle, with_options},
+    filters::{handle, handle_with_diem, with_diem, with_options},
+    metrics,
+    operations::{describe_script, Intent},
     options::Options,
+    pending::{self, Submission},
     types::{
-        AccountIdentifier, Amount, ConstructionCombineRequest, ConstructionCombineResponse,
+        AccountIdentifier, ConstructionCombineRequest, ConstructionCombineResponse,
         ConstructionDeriveRequest, ConstructionDeriveResponse, ConstructionHashRequest,
         ConstructionMetadata, ConstructionMetadataRequest, ConstructionMetadataResponse,
         ConstructionParseRequest, ConstructionParseResponse, ConstructionPayloadsRequest,
         ConstructionPayloadsResponse, ConstructionPreprocessRequest,
-        ConstructionPreprocessResponse, ConstructionSubmitRequest, Currency, CurveType,
-        MetadataOptions, Operation, OperationIdentifier, SignatureType, SigningPayload,
-        TransactionIdentifier, TransactionIdentifierResponse,
+        ConstructionPreprocessResponse, ConstructionSubmitRequest, CurveType, DeriveMetadata,
+        MetadataOptions, Operation, ParseMetadata, PublicKey, SignatureType, SigningPayload,
+        TransactionIdentifier, TransactionIdentifierMetadata, TransactionIdentifierResponse,
     },
 };
-use anyhow::anyhow;
 use diem_crypto::{
     ed25519::Ed25519PublicKey,
     ed25519::Ed25519Signature,
     hash::{CryptoHash, CryptoHasher},
     ValidCryptoMaterialStringExt,
 };
+use diem_json_rpc_client::views::AccountView;
 use diem_types::{
-    account_config::constants::coins,
     chain_id::ChainId,
     transaction::{
         authenticator::{AuthenticationKey, Scheme},
@@ -31,57 +33,56 @@ use diem_types::{
     },
 };
 use log::debug;
-use move_core_types::{
-    account_address::AccountAddress,
-    identifier::Identifier,
-    language_storage::{StructTag, TypeTag},
-};
+use move_core_types::account_address::AccountAddress;
 use std::{
     convert::TryInto,
-    str::FromStr,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use transaction_builder_generated::stdlib::{self, ScriptCall};
 use warp::Filter;
 
+const EXECUTION_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub fn routes(
     options: Options,
+    diem: Diem,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::post()
         .and(
             warp::path!("construction" / "derive")
                 .and(warp::body::json())
                 .and(with_options(options.clone()))
-                .and_then(handle(derive)),
+                .and_then(handle("/construction/derive", derive)),
         )
         .or(warp::path!("construction" / "preprocess")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(preprocess)))
+            .and_then(handle("/construction/preprocess", preprocess)))
         .or(warp::path!("construction" / "metadata")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(metadata)))
+            .and(with_diem(diem.clone()))
+            .and_then(handle_with_diem("/construction/metadata", metadata)))
         .or(warp::path!("construction" / "payloads")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(payloads)))
+            .and_then(handle("/construction/payloads", payloads)))
         .or(warp::path!("construction" / "parse")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(parse)))
+            .and_then(handle("/construction/parse", parse)))
         .or(warp::path!("construction" / "combine")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(combine)))
+            .and_then(handle("/construction/combine", combine)))
         .or(warp::path!("construction" / "hash")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(hash)))
+            .and_then(handle("/construction/hash", hash)))
         .or(warp::path!("construction" / "submit")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(submit)))
+            .and(with_diem(diem.clone()))
+            .and_then(handle_with_diem("/construction/submit", submit)))
 }
 
 async fn derive(
@@ -99,10 +100,8 @@ async fn derive(
 
     let public_key = Ed25519PublicKey::from_encoded_string(&derive_request.public_key.hex_bytes)
         .map_err(|_| ApiError::deserialization_failed("Ed25519PublicKey"))?;
-    let address = AuthenticationKey::ed25519(&public_key)
-        .derived_address()
-        .to_string()
-        .to_lowercase();
+    let auth_key = AuthenticationKey::ed25519(&public_key);
+    let address = auth_key.derived_address().to_string().to_lowercase();
 
     let sub_account = None;
     let account_identifier = AccountIdentifier {
@@ -110,7 +109,15 @@ async fn derive(
         sub_account,
     };
 
-    let response = ConstructionDeriveResponse { account_identifier };
+    let metadata = DeriveMetadata {
+        auth_key: hex::encode(auth_key.to_vec()),
+        auth_key_prefix: hex::encode(auth_key.prefix()),
+    };
+
+    let response = ConstructionDeriveResponse {
+        account_identifier,
+        metadata,
+    };
 
     Ok(response)
 }
@@ -128,12 +135,26 @@ async fn preprocess(
         return Err(ApiError::BadNetwork);
     }
 
-    let transfer = extract_transfer_from_operations(&preprocess_request.operations)
-        .map_err(|e| ApiError::BadTransferOperations(format!("{}", e)))?;
+    let intent = intent_from_operations(&preprocess_request.operations, &options)?;
+
+    let (receiver_address, amount, currency) = match &intent {
+        Intent::Transfer(transfer) => (
+            Some((&transfer.receiver).into()),
+            Some(transfer.amount),
+            Some(transfer.currency.clone()),
+        ),
+        _ => (None, None, None),
+    };
 
     let response = ConstructionPreprocessResponse {
         options: MetadataOptions {
-            sender_address: (&transfer.sender).into(),
+            sender_address: (&intent.sender()).into(),
+            receiver_address,
+            amount,
+            currency,
+            use_chain_sequence_number: preprocess_request
+                .metadata
+                .and_then(|metadata| metadata.use_chain_sequence_number),
         },
     };
 
@@ -141,9 +162,12 @@ async fn preprocess(
 }
 
 // In order to construct a transaction, we need the chain id and the account sequence number.
+// The sequence number skips over transactions submitted through the proxy that are still
+// pending. For payments, we also report whether the amount crosses the travel-rule threshold.
 async fn metadata(
     metadata_request: ConstructionMetadataRequest,
     options: Options,
+    diem: Diem,
 ) -> Result<ConstructionMetadataResponse, ApiError> {
     debug!("/construction/metadata");
 
@@ -154,27 +178,129 @@ async fn metadata(
         return Err(ApiError::BadNetwork);
     }
 
-    let address = metadata_request.options.sender_address;
+    let MetadataOptions {
+        sender_address,
+        receiver_address,
+        amount,
+        currency,
+        use_chain_sequence_number,
+    } = metadata_request.options;
+
+    let mut addresses = vec![sender_address.as_str()];
+    if let Some(receiver_address) = &receiver_address {
+        addresses.push(receiver_address);
+    }
 
-    let diem = Diem::new(&options.diem_endpoint);
-    let (account, metadata) = diem.get_account_with_metadata(&address).await?;
+    let (mut accounts, metadata) = diem.get_accounts_with_metadata(&addresses).await?;
 
-    if account.is_none() {
-        return Err(ApiError::AccountNotFound);
+    let sender = accounts.remove(0).ok_or(ApiError::AccountNotFound)?;
+    let receiver = accounts.pop();
+
+    if let Some(public_keys) = &metadata_request.public_keys {
+        check_auth_key(&sender, public_keys)?;
     }
+    check_payment(&sender, receiver, amount, currency.as_deref())?;
 
     let chain_id = metadata.chain_id;
-    let sequence_number = account.unwrap().sequence_number;
+    let sequence_number = if use_chain_sequence_number.unwrap_or(false) {
+        sender.sequence_number
+    } else {
+        let sender_address = sender_address
+            .parse::<AccountAddress>()
+            .map_err(|_| ApiError::deserialization_failed("AccountAddress"))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        pending::next_sequence_number(&sender_address, sender.sequence_number, now.as_secs())
+    };
+
+    // NOTE: the limit is denominated in XDX, and we treat amounts as if the
+    // exchange rate were 1, which holds for XUS and XDX.
+    let dual_attestation_limit = metadata.dual_attestation_limit;
+    let dual_attestation_required = match (amount, dual_attestation_limit) {
+        (Some(amount), Some(limit)) => Some(amount >= limit),
+        _ => None,
+    };
 
     let metadata = ConstructionMetadata {
         chain_id,
         sequence_number,
+        dual_attestation_limit,
+        dual_attestation_required,
+        travel_rule_metadata: None,
+        metadata_signature: None,
     };
     let response = ConstructionMetadataResponse { metadata };
 
     Ok(response)
 }
 
+/// Catches signers whose keys no longer match the account, e.g. after a key rotation
+fn check_auth_key(sender: &AccountView, public_keys: &[PublicKey]) -> Result<(), ApiError> {
+    let on_chain_auth_key = hex::decode(&sender.authentication_key.0)?;
+
+    for public_key in public_keys {
+        if public_key.curve_type != CurveType::Edwards25519 {
+            return Err(ApiError::BadSignatureType);
+        }
+
+        let public_key: Ed25519PublicKey = hex::decode(&public_key.hex_bytes)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| ApiError::deserialization_failed("Ed25519PublicKey"))?;
+        if AuthenticationKey::ed25519(&public_key).to_vec() != on_chain_auth_key {
+            return Err(ApiError::AuthKeyMismatch);
+        }
+    }
+
+    Ok(())
+}
+
+/// Catches payments that would abort on-chain, before any gas is spent. The
+/// receiver is only given for payments.
+fn check_payment(
+    sender: &AccountView,
+    receiver: Option<Option<AccountView>>,
+    amount: Option<u64>,
+    currency: Option<&str>,
+) -> Result<(), ApiError> {
+    if sender.is_frozen {
+        return Err(ApiError::AccountFrozen);
+    }
+
+    let currency = match currency {
+        Some(currency) => currency,
+        None => return Ok(()),
+    };
+
+    if let Some(receiver) = receiver {
+        let receiver = receiver.ok_or(ApiError::ReceiverNotFound)?;
+        if receiver.is_frozen {
+            return Err(ApiError::AccountFrozen);
+        }
+        if !receiver
+            .balances
+            .iter()
+            .any(|balance| balance.currency == currency)
+        {
+            return Err(ApiError::ReceiverMissingCurrency);
+        }
+    }
+
+    if let Some(amount) = amount {
+        // gas is paid in the currency of the payment
+        let max_gas_fee = consts::MAX_GAS_AMOUNT as u128 * consts::GAS_UNIT_PRICE as u128;
+        let balance = sender
+            .balances
+            .iter()
+            .find(|balance| balance.currency == currency)
+            .map_or(0, |balance| balance.amount);
+        if (balance as u128) < amount as u128 + max_gas_fee {
+            return Err(ApiError::InsufficientBalance);
+        }
+    }
+
+    Ok(())
+}
+
 async fn payloads(
     payloads_request: ConstructionPayloadsRequest,
     options: Options,
@@ -191,35 +317,33 @@ async fn payloads(
     let ConstructionMetadata {
         chain_id,
         sequence_number,
+        travel_rule_metadata,
+        metadata_signature,
+        ..
     } = payloads_request.metadata;
 
-    // The only payload we allow to construct is a single p2p payment.
+    let mut intent = intent_from_operations(&payloads_request.operations, &options)?;
 
-    // TODO: allow other currencies
-    let transfer = extract_transfer_from_operations(&payloads_request.operations)
-        .map_err(|e| ApiError::BadTransferOperations(format!("{}", e)))?;
+    // travel-rule attestation may be given either on the operations or as
+    // construction metadata
+    if let Intent::Transfer(transfer) = &mut intent {
+        let metadata_signature = match metadata_signature {
+            Some(signature) => hex::decode(signature)?,
+            None => vec![],
+        };
+        transfer.set_default_attestation(travel_rule_metadata, metadata_signature);
+    }
 
-    let sender = transfer.sender.clone();
-    let max_gas_amount = 10_000;
-    let gas_unit_price = 0;
-    let gas_currency_code = transfer.currency.clone();
+    let sender = intent.sender();
+    let max_gas_amount = consts::MAX_GAS_AMOUNT;
+    let gas_unit_price = consts::GAS_UNIT_PRICE;
+    let gas_currency_code = intent.currency().to_string();
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
-    let expiration_timestamp_secs = (now + Duration::from_secs(10)).as_secs();
-
-    let currency = TypeTag::Struct(StructTag {
-        address: AccountAddress::from_hex_literal("0x1").unwrap(),
-        module: Identifier::new(transfer.currency.clone()).unwrap(),
-        name: Identifier::new(transfer.currency.clone()).unwrap(),
-        type_params: vec![],
-    });
-    let payee = transfer.receiver.clone();
-    let script = stdlib::encode_peer_to_peer_with_metadata_script(
-        currency,
-        payee,
-        transfer.amount,
-        vec![],
-        vec![],
-    );
+    let expiration_timestamp_secs = now.as_secs() + consts::TRANSACTION_EXPIRATION_SECS;
+
+    let script = intent
+        .into_script()
+        .map_err(|e| ApiError::BadTransferOperations(format!("{}", e)))?;
 
     let raw_transaction = RawTransaction::new_script(
         sender,
@@ -252,6 +376,21 @@ async fn payloads(
     Ok(response)
 }
 
+/// Builds the intent of the operations, unless it is a treasury operation and
+/// those aren't enabled
+fn intent_from_operations(operations: &[Operation], options: &Options) -> Result<Intent, ApiError> {
+    let intent = Intent::from_operations(operations)
+        .map_err(|e| ApiError::BadTransferOperations(format!("{}", e)))?;
+
+    if intent.is_treasury_operation() && !options.enable_treasury_operations {
+        return Err(ApiError::BadTransferOperations(
+            "treasury operations are disabled".to_string(),
+        ));
+    }
+
+    Ok(intent)
+}
+
 async fn parse(
     parse_request: ConstructionParseRequest,
     options: Options,
@@ -292,78 +431,38 @@ async fn parse(
         (raw_transaction, vec![])
     };
 
-    // verify that script is a peer to peer payment
-    let (currency, payee, amount) =
-        if let TransactionPayload::Script(script) = raw_transaction.clone().into_payload() {
-            if let Some(ScriptCall::PeerToPeerWithMetadata {
-                currency,
-                payee,
-                amount,
-                ..
-            }) = ScriptCall::decode(&script)
-            {
-                (currency, payee, amount)
-            } else {
-                return Err(ApiError::BadTransactionScript);
-            }
-        } else {
-            return Err(ApiError::BadTransactionPayload);
-        };
-
-    if currency != coins::xus_tag() {
-        return Err(ApiError::BadCoin);
+    // NOTE: if the network isn't named after a chain, we can't check the chain id offline
+    if let Some(chain_id) = options.chain_id() {
+        if raw_transaction.chain_id() != chain_id {
+            return Err(ApiError::ChainIdMismatch);
+        }
     }
-    let currency_code = "XUS".to_string();
-
-    let operations = vec![
-        Operation {
-            operation_identifier: OperationIdentifier {
-                index: 0,
-                network_index: None,
-            },
-            related_operations: None,
-            type_: "sentpayment".to_string(),
-            status: None,
-            account: Some(AccountIdentifier {
-                address: (&raw_transaction.sender()).into(),
-                sub_account: None,
-            }),
-            amount: Some(Amount {
-                value: format!("-{}", amount),
-                currency: Currency {
-                    symbol: currency_code.clone(),
-                    decimals: 6,
-                },
-            }),
-        },
-        Operation {
-            operation_identifier: OperationIdentifier {
-                index: 1,
-                network_index: None,
-            },
-            related_operations: Some(vec![OperationIdentifier {
-                index: 0,
-                network_index: None,
-            }]),
-            type_: "receivedpayment".to_string(),
-            status: None,
-            account: Some(AccountIdentifier {
-                address: (&payee).into(),
-                sub_account: None,
-            }),
-            amount: Some(Amount {
-                value: format!("{}", amount),
-                currency: Currency {
-                    symbol: currency_code.clone(),
-                    decimals: 6,
-                },
-            }),
-        },
-    ];
+
+    let script = match raw_transaction.clone().into_payload() {
+        TransactionPayload::Script(script) => script,
+        _ => return Err(ApiError::BadTransactionPayload),
+    };
+    let operations = Intent::from_script(
+        raw_transaction.sender(),
+        raw_transaction.gas_currency_code(),
+        &script,
+    )?
+    .into_operations();
+
+    let metadata = ParseMetadata {
+        sequence_number: raw_transaction.sequence_number(),
+        expiration_timestamp_secs: raw_transaction.expiration_timestamp_secs(),
+        max_gas_amount: raw_transaction.max_gas_amount(),
+        gas_unit_price: raw_transaction.gas_unit_price(),
+        gas_currency: raw_transaction.gas_currency_code().to_string(),
+        chain_id: raw_transaction.chain_id().id(),
+        script_name: describe_script(&script).map(|script| script.name),
+    };
 
     let response = ConstructionParseResponse {
         operations,
         account_identifier_signers,
+        metadata,
     };
 
     Ok(response)
@@ -441,6 +540,7 @@ async fn hash(
 
     let response = TransactionIdentifierResponse {
         transaction_identifier,
+        metadata: None,
     };
 
     Ok(response)
@@ -449,6 +549,7 @@ async fn hash(
 async fn submit(
     submit_request: ConstructionSubmitRequest,
     options: Options,
+    diem: Diem,
 ) -> Result<TransactionIdentifierResponse, ApiError> {
     debug!("/construction/submit");
 
@@ -463,132 +564,246 @@ async fn submit(
     let signed_transaction: SignedTransaction = bcs::from_bytes(&signed_bytes)
         .map_err(|_| ApiError::deserialization_failed("SignedTransaction"))?;
 
-    let diem = Diem::new(&options.diem_endpoint);
-    diem.submit(&signed_transaction).await?;
-
-    let hash = Transaction::UserTransaction(signed_transaction)
+    let sender = signed_transaction.sender();
+    let sequence_number = signed_transaction.sequence_number();
+    let expiration_secs = signed_transaction.expiration_timestamp_secs();
+    let hash = Transaction::UserTransaction(signed_transaction.clone())
         .hash()
         .to_hex();
 
-    let transaction_identifier = TransactionIdentifier { hash };
+    // A retried submission gets the outcome of the original one once it is
+    // known. Until then it is sent again, since the original may never have
+    // reached the node.
+    if let Some(submission) = pending::find_submission(&hash) {
+        if let Some(response) = resubmission(&diem, submission, hash.clone()).await? {
+            return Ok(response);
+        }
+    }
 
-    let response = TransactionIdentifierResponse {
-        transaction_identifier,
-    };
+    // Reject transactions the node would reject anyway, with clearer errors
 
-    Ok(response)
-}
+    signed_transaction
+        .clone()
+        .check_signature()
+        .map_err(|_| rejected(ApiError::BadSignature))?;
 
-#[derive(Clone, Copy, Debug)]
-enum Value {
-    Credit(u64),
-    Debit(u64),
-}
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    if expiration_secs <= now.as_secs() {
+        return Err(rejected(ApiError::TransactionExpired));
+    }
 
-impl Value {
-    fn reconciles(&self, value: Value) -> bool {
-        match (*self, value) {
-            (Value::Credit(c), Value::Debit(d)) => c == d,
-            (Value::Debit(d), Value::Credit(c)) => d == c,
-            _ => false,
-        }
+    let (account, metadata) = diem.get_account_with_metadata(&sender.to_string()).await?;
+
+    // NOTE: if the network isn't named after a chain, we trust the node
+    let chain_id = options
+        .chain_id()
+        .unwrap_or_else(|| ChainId::new(metadata.chain_id));
+    if signed_transaction.chain_id() != chain_id {
+        return Err(rejected(ApiError::ChainIdMismatch));
     }
 
-    fn amount(self) -> u64 {
-        match self {
-            Value::Credit(v) => v,
-            Value::Debit(v) => v,
-        }
+    // An earlier submission of the same transaction may have gone through
+    // without the proxy hearing back, or through another proxy
+    let account = account.ok_or_else(|| rejected(ApiError::AccountNotFound))?;
+    if sequence_number < account.sequence_number {
+        return match find_committed(&diem, sender, sequence_number, hash).await? {
+            Some(response) => Ok(response),
+            None => Err(rejected(ApiError::SequenceNumberTooOld)),
+        };
     }
-}
 
-impl FromStr for Value {
-    type Err = anyhow::Error;
+    // The submission is recorded before it reaches the node, so that it is
+    // recognized when retried after a timeout
+    let resubmitted = pending::record_submission(
+        &hash,
+        sender,
+        sequence_number,
+        expiration_secs,
+        now.as_secs(),
+    )
+    .is_some();
+
+    if let Err(e) = diem.submit(&signed_transaction).await {
+        let is_rejection = e.is_rejection();
+        let error = ApiError::from(e);
+
+        // the node already holds this transaction, from an earlier or a
+        // concurrent submission
+        if resubmitted && matches!(error, ApiError::TransactionAlreadyPending) {
+            metrics::record_submission("duplicate");
+            return Ok(duplicate_response(hash, pending_status()));
+        }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() {
-            return Err(anyhow!("empty input"));
+        // the submission is kept when its outcome is unknown, like on timeouts
+        if is_rejection {
+            pending::forget_submission(&hash);
         }
 
-        let v = s.parse::<i128>()?;
-        match v < 0 {
-            true => Ok(Value::Debit((-v) as u64)),
-            false => Ok(Value::Credit(v as u64)),
+        if matches!(
+            error,
+            ApiError::SequenceNumberTooOld | ApiError::TransactionAlreadyPending
+        ) {
+            if let Some(response) = find_committed(&diem, sender, sequence_number, hash).await? {
+                return Ok(response);
+            }
         }
+
+        metrics::record_submission("rejected");
+        return Err(error);
     }
-}
+    metrics::record_submission("accepted");
+
+    let wait_for_execution = submit_request
+        .metadata
+        .and_then(|metadata| metadata.wait_for_execution)
+        .unwrap_or(false);
+    let metadata = if wait_for_execution {
+        let timeout = Duration::from_secs(options.submit_wait_timeout_secs);
+        Some(wait_for_transaction(&diem, sender, sequence_number, &hash, timeout).await?)
+    } else {
+        None
+    };
 
-struct Transfer {
-    sender: AccountAddress,
-    receiver: AccountAddress,
-    amount: u64,
-    currency: String,
+    let transaction_identifier = TransactionIdentifier { hash };
+
+    let response = TransactionIdentifierResponse {
+        transaction_identifier,
+        metadata,
+    };
+
+    Ok(response)
 }
 
-fn extract_transfer_from_operations(operations: &[Operation]) -> Result<Transfer, anyhow::Error> {
-    if operations.len() != 2 {
-        return Err(anyhow!("wrong number of ops"));
-    }
+/// Counts a submission rejected before it reaches the node
+fn rejected(error: ApiError) -> ApiError {
+    metrics::record_submission("rejected");
+    error
+}
 
-    let is_p2p = match (operations[0].type_.as_ref(), operations[1].type_.as_ref()) {
-        ("sentpayment", "receivedpayment") => true,
-        ("receivedpayment", "sentpayment") => true,
-        _ => false,
+/// Answers a transaction submitted again with the outcome of the original
+/// submission, if it is known. Nothing is returned while the transaction is
+/// still pending.
+async fn resubmission(
+    diem: &Diem,
+    submission: Submission,
+    hash: String,
+) -> Result<Option<TransactionIdentifierResponse>, ApiError> {
+    let metadata = match submission.outcome {
+        Some(outcome) => outcome,
+        None => {
+            transaction_status(diem, submission.sender, submission.sequence_number, &hash).await?
+        }
     };
-
-    if !is_p2p {
-        return Err(anyhow!("operations don't represent a transfer"));
+    if metadata.status == "pending" {
+        return Ok(None);
     }
 
-    if operations[0].account.is_none()
-        || operations[0].amount.is_none()
-        || operations[1].account.is_none()
-        || operations[1].amount.is_none()
-    {
-        return Err(anyhow!("accounts/amounts missing"));
+    debug!("transaction {} was already submitted", hash);
+    metrics::record_submission("duplicate");
+    Ok(Some(duplicate_response(hash, metadata)))
+}
+
+/// Answers a transaction that is already committed as a duplicate
+async fn find_committed(
+    diem: &Diem,
+    sender: AccountAddress,
+    sequence_number: u64,
+    hash: String,
+) -> Result<Option<TransactionIdentifierResponse>, ApiError> {
+    let metadata = transaction_status(diem, sender, sequence_number, &hash).await?;
+    if metadata.status != "committed" {
+        return Ok(None);
     }
 
-    let (send_account, send_amount, recv_account, recv_amount) =
-        if operations[0].type_ == "sentpayment" {
-            (
-                operations[0].account.as_ref().unwrap(),
-                operations[0].amount.as_ref().unwrap(),
-                operations[1].account.as_ref().unwrap(),
-                operations[1].amount.as_ref().unwrap(),
-            )
-        } else {
-            (
-                operations[1].account.as_ref().unwrap(),
-                operations[1].amount.as_ref().unwrap(),
-                operations[0].account.as_ref().unwrap(),
-                operations[0].amount.as_ref().unwrap(),
-            )
-        };
+    debug!("transaction {} was already committed", hash);
+    metrics::record_submission("duplicate");
+    Ok(Some(duplicate_response(hash, metadata)))
+}
 
-    if send_amount.currency != recv_amount.currency {
-        return Err(anyhow!("mismatched currencies in ops"));
+fn duplicate_response(
+    hash: String,
+    metadata: TransactionIdentifierMetadata,
+) -> TransactionIdentifierResponse {
+    TransactionIdentifierResponse {
+        transaction_identifier: TransactionIdentifier { hash },
+        metadata: Some(TransactionIdentifierMetadata {
+            duplicate: Some(true),
+            ..metadata
+        }),
     }
+}
 
-    let send_value = send_amount.value.parse::<Value>()?;
-    let recv_value = recv_amount.value.parse::<Value>()?;
+/// Polls the status of a submitted transaction until it is no longer pending
+/// or the timeout passes
+async fn wait_for_transaction(
+    diem: &Diem,
+    sender: AccountAddress,
+    sequence_number: u64,
+    hash: &str,
+    timeout: Duration,
+) -> Result<TransactionIdentifierMetadata, ApiError> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let metadata = transaction_status(diem, sender, sequence_number, hash).await?;
+        if metadata.status != "pending" {
+            return Ok(metadata);
+        }
 
-    if let Value::Credit(_) = send_value {
-        return Err(anyhow!("can't send negative amounts"));
-    }
+        if Instant::now() >= deadline {
+            debug!("timed out waiting for transaction {}", hash);
+            return Ok(metadata);
+        }
 
-    if !send_value.reconciles(recv_value) {
-        return Err(anyhow!("send and recv amounts don't net out"));
+        tokio::time::delay_for(EXECUTION_POLL_INTERVAL).await;
     }
+}
 
-    let sender = send_account.address.parse::<AccountAddress>()?;
-    let receiver = recv_account.address.parse::<AccountAddress>()?;
-    let amount = send_value.amount();
-    let currency = send_amount.currency.symbol.clone();
+/// Looks up the transaction with the given sender and sequence number. Once
+/// it is no longer pending, the outcome is recorded for retried submissions.
+async fn transaction_status(
+    diem: &Diem,
+    sender: AccountAddress,
+    sequence_number: u64,
+    hash: &str,
+) -> Result<TransactionIdentifierMetadata, ApiError> {
+    let view = diem
+        .get_account_transaction(sender, sequence_number, false)
+        .await?;
+
+    let metadata = match view {
+        Some(view) if view.hash.to_string() == hash => TransactionIdentifierMetadata {
+            status: "committed".to_string(),
+            duplicate: None,
+            version: Some(view.version),
+            vm_status: Some(diem::vmstatus_to_str(&view.vm_status).to_string()),
+            gas_used: Some(view.gas_used),
+        },
+        // another transaction with the same sequence number was committed
+        Some(view) => {
+            debug!("transaction {} was replaced by {}", hash, view.hash);
+            TransactionIdentifierMetadata {
+                status: "replaced".to_string(),
+                duplicate: None,
+                version: None,
+                vm_status: None,
+                gas_used: None,
+            }
+        }
+        None => return Ok(pending_status()),
+    };
 
-    Ok(Transfer {
-        sender,
-        receiver,
-        amount,
-        currency,
-    })
+    pending::record_outcome(hash, metadata.clone());
+
+    Ok(metadata)
+}
+
+fn pending_status() -> TransactionIdentifierMetadata {
+    TransactionIdentifierMetadata {
+        status: "pending".to_string(),
+        duplicate: None,
+        version: None,
+        vm_status: None,
+        gas_used: None,
+    }
 }
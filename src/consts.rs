@@ -2,3 +2,6 @@ pub const BLOCKCHAIN: &str = "diem";
 pub const MIDDLEWARE_VERSION: &str = "1.0.0";
 pub const NODE_VERSION: &str = "0.1";
 pub const ROSETTA_VERSION: &str = "1.3.1";
+pub const MAX_GAS_AMOUNT: u64 = 10_000;
+pub const GAS_UNIT_PRICE: u64 = 0;
+pub const TRANSACTION_EXPIRATION_SECS: u64 = 10;
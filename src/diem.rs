@@ -1,12 +1,24 @@
-use crate::error::ApiError;
+use crate::{error::ApiError, metrics, options::Options};
 use diem_json_rpc_client::{
     views::{AccountView, MetadataView, TransactionView, VMStatusView},
     AccountAddress, JsonRpcAsyncClient, JsonRpcAsyncClientError, JsonRpcBatch, JsonRpcResponse,
     SignedTransaction,
 };
+use diem_json_rpc_types::errors::ErrorData;
+use futures::future::join_all;
+use log::{error, warn};
+use move_core_types::vm_status::StatusCode;
 use std::fmt::Display;
 use std::str::FromStr;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
+use tokio::{sync::Semaphore, time::timeout};
 use url::Url;
 
 #[derive(Debug, Error)]
@@ -17,9 +29,90 @@ pub enum DiemError {
     RequestFailed(#[from] anyhow::Error),
     #[error("unexpected response (expected {expected:?}, found {found:?})")]
     UnexpectedResponse { expected: String, found: String },
+    #[error("submission rejected: {0:?}")]
+    SubmissionRejected(SubmissionError),
+    #[error("ledger mismatch: {0}")]
+    LedgerMismatch(String),
+}
+
+/// Reasons for the node to reject a transaction submission
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SubmissionError {
+    SequenceNumberTooOld,
+    SequenceNumberTooNew,
+    InsufficientBalanceForGas,
+    InvalidAuthKey,
+    InvalidSignature,
+    BadChainId,
+    TransactionExpired,
+    SendingAccountDoesNotExist,
+    SendingAccountFrozen,
+    MempoolFull,
+    TooManyPendingTransactions,
+    TransactionAlreadyPending,
+}
+
+// JSON-RPC server error codes for submissions rejected by the VM or by mempool
+const VM_VALIDATION_ERROR: i16 = -32001;
+const MEMPOOL_INVALID_SEQ_NUMBER: i16 = -32007;
+const MEMPOOL_IS_FULL: i16 = -32008;
+const MEMPOOL_TOO_MANY_TRANSACTIONS: i16 = -32009;
+const MEMPOOL_INVALID_UPDATE: i16 = -32010;
+const MEMPOOL_VM_ERROR: i16 = -32011;
+
+impl SubmissionError {
+    /// Recognizes the VM status or mempool status of a rejected submission
+    fn from_json_rpc_error(error: &JsonRpcAsyncClientError) -> Option<SubmissionError> {
+        let error = match error {
+            JsonRpcAsyncClientError::JsonRpcError(error) => error,
+            _ => return None,
+        };
+
+        match error.code {
+            MEMPOOL_INVALID_SEQ_NUMBER => Some(SubmissionError::SequenceNumberTooOld),
+            MEMPOOL_IS_FULL => Some(SubmissionError::MempoolFull),
+            MEMPOOL_TOO_MANY_TRANSACTIONS => Some(SubmissionError::TooManyPendingTransactions),
+            MEMPOOL_INVALID_UPDATE => Some(SubmissionError::TransactionAlreadyPending),
+            // the VM status is given as the error data
+            VM_VALIDATION_ERROR | MEMPOOL_VM_ERROR => match &error.data {
+                Some(ErrorData::StatusCode(status)) => SubmissionError::from_vm_status(status),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn from_vm_status(status: &StatusCode) -> Option<SubmissionError> {
+        match status {
+            StatusCode::SEQUENCE_NUMBER_TOO_OLD => Some(SubmissionError::SequenceNumberTooOld),
+            StatusCode::SEQUENCE_NUMBER_TOO_NEW => Some(SubmissionError::SequenceNumberTooNew),
+            StatusCode::INSUFFICIENT_BALANCE_FOR_TRANSACTION_FEE => {
+                Some(SubmissionError::InsufficientBalanceForGas)
+            }
+            StatusCode::INVALID_AUTH_KEY => Some(SubmissionError::InvalidAuthKey),
+            StatusCode::INVALID_SIGNATURE => Some(SubmissionError::InvalidSignature),
+            StatusCode::BAD_CHAIN_ID => Some(SubmissionError::BadChainId),
+            StatusCode::TRANSACTION_EXPIRED => Some(SubmissionError::TransactionExpired),
+            StatusCode::SENDING_ACCOUNT_DOES_NOT_EXIST => {
+                Some(SubmissionError::SendingAccountDoesNotExist)
+            }
+            StatusCode::SENDING_ACCOUNT_FROZEN => Some(SubmissionError::SendingAccountFrozen),
+            _ => None,
+        }
+    }
 }
 
 impl DiemError {
+    /// Whether the node answered that it didn't accept a submission, as
+    /// opposed to the submission's outcome being unknown
+    pub fn is_rejection(&self) -> bool {
+        match self {
+            DiemError::SubmissionRejected(_) => true,
+            DiemError::JsonRpcResponse(error) => !is_transient(error),
+            _ => false,
+        }
+    }
+
     pub fn unexpected_response<D1, D2>(expected: D1, found: D2) -> DiemError
     where
         D1: Display,
@@ -32,6 +125,13 @@ impl DiemError {
     }
 }
 
+/// Fullnodes disagreeing on committed data means that one of them is
+/// misbehaving, which operators need to know about
+fn ledger_mismatch(details: String) -> DiemError {
+    error!("ledger mismatch: {}", details);
+    DiemError::LedgerMismatch(details)
+}
+
 impl std::convert::From<DiemError> for warp::reject::Rejection {
     fn from(diem_error: DiemError) -> Self {
         let api_error: ApiError = diem_error.into();
@@ -45,38 +145,326 @@ impl std::convert::From<JsonRpcAsyncClientError> for DiemError {
     }
 }
 
-pub struct Diem {
+/// An account, and the latest transaction of the ledger version it was read at
+pub struct AccountSnapshot {
+    pub account: Option<AccountView>,
+    pub latest_transaction: TransactionView,
+}
+
+/// Number of transactions read from the latest version seen, to find the
+/// latest transaction in the same batch as the latest metadata. A batch is
+/// answered from a single ledger version, so this is consistent.
+const LATEST_TRANSACTIONS_LIMIT: u64 = 100;
+
+/// What /network/status reports, read from a single ledger version
+pub struct LedgerStatus {
+    pub metadata: MetadataView,
+    pub genesis_transaction: TransactionView,
+    pub latest_transaction: TransactionView,
+    pub peer_count: u64,
+}
+
+/// A fullnode, along with the state of its circuit breaker
+struct Endpoint {
+    url: Url,
     client: JsonRpcAsyncClient,
+    health: Mutex<Health>,
 }
 
-impl Diem {
-    pub fn new(endpoint: &Url) -> Diem {
-        Diem {
-            client: JsonRpcAsyncClient::new(endpoint.clone()),
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    /// Set when the circuit opens, and the endpoint is skipped until then
+    open_until: Option<Instant>,
+}
+
+impl Endpoint {
+    /// Whether the endpoint is skipped. Once the cooldown passes the circuit
+    /// is half-open: the endpoint is tried again, and a single failure opens
+    /// the circuit again.
+    fn is_open(&self, now: Instant, retry_policy: &RetryPolicy) -> bool {
+        let mut health = self.health.lock().unwrap();
+        match health.open_until {
+            Some(open_until) if open_until > now => true,
+            Some(_) => {
+                health.open_until = None;
+                health.consecutive_failures =
+                    retry_policy.circuit_breaker_threshold.saturating_sub(1);
+                false
+            }
+            None => false,
         }
     }
 
-    pub async fn get_metadata(&self, version: Option<u64>) -> Result<MetadataView, DiemError> {
-        let mut batch = JsonRpcBatch::new();
-        batch.add_get_metadata_request(version);
+    fn open_until(&self) -> Option<Instant> {
+        self.health.lock().unwrap().open_until
+    }
 
-        let mut result = self.client.execute(batch).await?;
+    fn record_success(&self) {
+        *self.health.lock().unwrap() = Health::default();
+    }
 
-        if result.len() != 1 {
-            return Err(DiemError::unexpected_response(
-                "1 result",
-                format!("{} results", result.len()),
-            ));
+    fn record_failure(&self, retry_policy: &RetryPolicy) {
+        let mut health = self.health.lock().unwrap();
+        health.consecutive_failures += 1;
+
+        if health.consecutive_failures >= retry_policy.circuit_breaker_threshold {
+            warn!(
+                "{} failed {} times in a row, skipping it for {:?}",
+                self.url, health.consecutive_failures, retry_policy.circuit_breaker_cooldown
+            );
+            health.open_until = Some(Instant::now() + retry_policy.circuit_breaker_cooldown);
         }
+    }
+}
 
-        let result = result.remove(0)?;
-        match result {
-            JsonRpcResponse::MetadataViewResponse(metadata) => Ok(metadata),
-            _ => Err(DiemError::unexpected_response(
-                "MetadataViewResponse",
-                "other",
-            )),
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    circuit_breaker_threshold: u32,
+    circuit_breaker_cooldown: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter, so that concurrent retries don't
+    /// hit the fullnodes in lockstep
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .checked_mul(1 << attempt.min(16))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        delay.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// Errors that say nothing about the request itself, so that it may succeed
+/// on another attempt or another endpoint
+fn is_transient(error: &JsonRpcAsyncClientError) -> bool {
+    !matches!(error, JsonRpcAsyncClientError::JsonRpcError(_))
+}
+
+/// Client of the Diem endpoints, shared by all requests so that connections
+/// are pooled
+#[derive(Clone)]
+pub struct Diem {
+    endpoints: Arc<Vec<Endpoint>>,
+    /// Bounds the number of requests in flight, and so of open connections
+    permits: Arc<Semaphore>,
+    retry_policy: RetryPolicy,
+    /// Number of endpoints that version-pinned reads are compared across
+    cross_check_endpoints: usize,
+    cross_check_timeout: Duration,
+    /// Highest ledger version seen in the latest metadata of an endpoint
+    latest_version: Arc<AtomicU64>,
+}
+
+impl Diem {
+    pub fn new(options: &Options) -> Result<Diem, DiemError> {
+        let http_client = reqwest::Client::builder()
+            .pool_max_idle_per_host(options.max_idle_connections)
+            .pool_idle_timeout(Duration::from_secs(options.idle_connection_timeout_secs))
+            .tcp_keepalive(Duration::from_secs(options.tcp_keepalive_secs))
+            .build()
+            .map_err(anyhow::Error::from)?;
+
+        let endpoints = options
+            .diem_endpoint
+            .iter()
+            .map(|url| Endpoint {
+                url: url.clone(),
+                client: JsonRpcAsyncClient::new_with_client(http_client.clone(), url.clone()),
+                health: Mutex::new(Health::default()),
+            })
+            .collect();
+
+        Ok(Diem {
+            endpoints: Arc::new(endpoints),
+            permits: Arc::new(Semaphore::new(options.max_concurrent_requests)),
+            retry_policy: RetryPolicy {
+                max_retries: options.max_retries,
+                base_delay: Duration::from_millis(options.retry_base_delay_ms),
+                max_delay: Duration::from_millis(options.retry_max_delay_ms),
+                circuit_breaker_threshold: options.circuit_breaker_threshold,
+                circuit_breaker_cooldown: Duration::from_secs(
+                    options.circuit_breaker_cooldown_secs,
+                ),
+            },
+            cross_check_endpoints: options.cross_check_endpoints,
+            cross_check_timeout: Duration::from_millis(options.cross_check_timeout_ms),
+            latest_version: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// The endpoint to send the next attempt of a request to: the first one
+    /// in order of preference whose circuit isn't open, skipping the ones
+    /// already tried for the request unless they all were. When every
+    /// circuit is open, the one that closes first is tried.
+    fn healthiest_endpoint(&self, tried: &[&Endpoint]) -> &Endpoint {
+        let now = Instant::now();
+        let available = || {
+            self.endpoints
+                .iter()
+                .filter(move |endpoint| !endpoint.is_open(now, &self.retry_policy))
+        };
+
+        available()
+            .find(|endpoint| !tried.iter().any(|other| std::ptr::eq(*other, *endpoint)))
+            .or_else(|| available().next())
+            .or_else(|| {
+                self.endpoints
+                    .iter()
+                    .min_by_key(|endpoint| endpoint.open_until())
+            })
+            .expect("at least one endpoint is required")
+    }
+
+    /// Sends a batch to an endpoint, labelled by `method` in metrics
+    async fn execute_on(
+        &self,
+        endpoint: &Endpoint,
+        method: &str,
+        batch: JsonRpcBatch,
+    ) -> Result<Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>, JsonRpcAsyncClientError>
+    {
+        let result = {
+            let _permit = self.permits.acquire().await;
+            let started = Instant::now();
+            let result = endpoint.client.execute(batch).await;
+            metrics::record_json_rpc(method, started.elapsed(), result.is_ok());
+            result
+        };
+
+        match &result {
+            Err(error) if is_transient(error) => endpoint.record_failure(&self.retry_policy),
+            _ => endpoint.record_success(),
         }
+
+        result
+    }
+
+    /// Executes a read, retrying transient failures on the next healthiest
+    /// endpoint. Returns the endpoint that answered along with the result.
+    async fn execute_retrying(
+        &self,
+        method: &str,
+        batch: JsonRpcBatch,
+    ) -> (
+        &Endpoint,
+        Result<Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>, JsonRpcAsyncClientError>,
+    ) {
+        let mut attempt = 0;
+        let mut tried = vec![];
+        loop {
+            let endpoint = self.healthiest_endpoint(&tried);
+            match self.execute_on(endpoint, method, batch.clone()).await {
+                Err(error) if is_transient(&error) && attempt < self.retry_policy.max_retries => {
+                    warn!("request to {} failed, retrying: {}", endpoint.url, error);
+                    tokio::time::delay_for(self.retry_policy.backoff(attempt)).await;
+                    tried.push(endpoint);
+                    attempt += 1;
+                }
+                result => return (endpoint, result),
+            }
+        }
+    }
+
+    async fn execute(
+        &self,
+        method: &str,
+        batch: JsonRpcBatch,
+    ) -> Result<Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>, JsonRpcAsyncClientError>
+    {
+        self.execute_retrying(method, batch).await.1
+    }
+
+    /// Finds the transaction at the version of `metadata` among the ones
+    /// read along with it. When the ledger moved too far since the last
+    /// read, it is fetched from `endpoint` instead: the endpoint that
+    /// reported that version, since another fullnode may not have reached it.
+    async fn find_latest_transaction(
+        &self,
+        endpoint: &Endpoint,
+        method: &str,
+        metadata: &MetadataView,
+        transactions: Vec<TransactionView>,
+    ) -> Result<TransactionView, DiemError> {
+        let latest_transaction = transactions
+            .into_iter()
+            .find(|transaction| transaction.version == metadata.version);
+        metrics::record_latest_transaction_lookup(latest_transaction.is_some());
+        if let Some(latest_transaction) = latest_transaction {
+            return Ok(latest_transaction);
+        }
+
+        self.get_transaction_on(endpoint, method, metadata.version)
+            .await
+    }
+
+    /// Fetches the transaction at `version` from `endpoint`
+    async fn get_transaction_on(
+        &self,
+        endpoint: &Endpoint,
+        method: &str,
+        version: u64,
+    ) -> Result<TransactionView, DiemError> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_transactions_request(version, 1, false);
+
+        let responses = self.execute_on(endpoint, method, batch).await?;
+        transactions_from(single_response(responses)?)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DiemError::unexpected_response("latest transaction", "none"))
+    }
+
+    /// Sends a read to the other endpoints taking part in cross-checking.
+    /// Endpoints that fail, that don't answer in time, or that lag behind and
+    /// don't have the data yet, are skipped.
+    async fn cross_check_results(
+        &self,
+        answered_by: &Endpoint,
+        method: &str,
+        batch: JsonRpcBatch,
+    ) -> Vec<(
+        &Endpoint,
+        Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>,
+    )> {
+        let now = Instant::now();
+        let requests = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| {
+                !std::ptr::eq(*endpoint, answered_by) && !endpoint.is_open(now, &self.retry_policy)
+            })
+            .take(self.cross_check_endpoints.saturating_sub(1))
+            .map(|endpoint| {
+                let batch = batch.clone();
+                let request = self.execute_on(endpoint, method, batch);
+                async move { (endpoint, timeout(self.cross_check_timeout, request).await) }
+            });
+
+        join_all(requests)
+            .await
+            .into_iter()
+            .filter_map(|(endpoint, result)| match result {
+                Ok(Ok(result)) => Some((endpoint, result)),
+                Ok(Err(error)) => {
+                    warn!("couldn't cross-check with {}: {}", endpoint.url, error);
+                    None
+                }
+                Err(_) => {
+                    // a hung fullnode counts as failing, so that its circuit opens
+                    warn!(
+                        "couldn't cross-check with {}: no answer after {:?}",
+                        endpoint.url, self.cross_check_timeout
+                    );
+                    endpoint.record_failure(&self.retry_policy);
+                    None
+                }
+            })
+            .collect()
     }
 
     pub async fn get_transactions(
@@ -88,48 +476,149 @@ impl Diem {
         let mut batch = JsonRpcBatch::new();
         batch.add_get_transactions_request(start_version, limit, include_events);
 
-        let mut result = self.client.execute(batch).await?;
+        let (endpoint, result) = self
+            .execute_retrying("get_transactions", batch.clone())
+            .await;
+        let transactions = transactions_from(single_response(result?)?)?;
 
-        if result.len() != 1 {
-            return Err(DiemError::unexpected_response(
-                "1 result",
-                format!("{} results", result.len()),
-            ));
+        for (other, result) in self
+            .cross_check_results(endpoint, "get_transactions", batch)
+            .await
+        {
+            match single_response(result).and_then(transactions_from) {
+                Ok(other_transactions) => {
+                    compare_transactions(&transactions, &other_transactions, endpoint, other)?
+                }
+                Err(error) => warn!("couldn't cross-check with {}: {}", other.url, error),
+            }
         }
 
-        let result = result.remove(0)?;
-        match result {
-            JsonRpcResponse::TransactionsResponse(views) => Ok(views),
-            _ => Err(DiemError::unexpected_response(
-                "TransactionsResponse",
-                "other",
-            )),
+        Ok(transactions)
+    }
+
+    /// Fetches the metadata along with a range of transactions, from the same
+    /// ledger version
+    pub async fn get_metadata_with_transactions(
+        &self,
+        version: Option<u64>,
+        start_version: u64,
+        limit: u64,
+        include_events: bool,
+    ) -> Result<(MetadataView, Vec<TransactionView>), DiemError> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_metadata_request(version);
+        batch.add_get_transactions_request(start_version, limit, include_events);
+
+        let (endpoint, result) = self
+            .execute_retrying("get_metadata_with_transactions", batch.clone())
+            .await;
+        let (metadata, transactions) = metadata_with_transactions_from(result?)?;
+
+        for (other, result) in self
+            .cross_check_results(endpoint, "get_metadata_with_transactions", batch)
+            .await
+        {
+            match metadata_with_transactions_from(result) {
+                Ok((other_metadata, other_transactions)) => {
+                    if version.is_some() {
+                        compare_metadata(&metadata, &other_metadata, endpoint, other)?;
+                    }
+                    compare_transactions(&transactions, &other_transactions, endpoint, other)?;
+                }
+                Err(error) => warn!("couldn't cross-check with {}: {}", other.url, error),
+            }
         }
+
+        Ok((metadata, transactions))
+    }
+
+    /// The highest ledger version seen so far, or 0 before the latest
+    /// metadata is first read
+    pub fn latest_version(&self) -> u64 {
+        self.latest_version.load(Ordering::Relaxed)
     }
 
-    pub async fn get_network_status(&self) -> Result<u64, DiemError> {
+    fn record_latest_version(&self, version: u64) {
+        let previous = self.latest_version.fetch_max(version, Ordering::Relaxed);
+        metrics::set_ledger_version(previous.max(version));
+    }
+
+    /// Fetches the latest metadata of the ledger
+    pub async fn get_metadata(&self) -> Result<MetadataView, DiemError> {
         let mut batch = JsonRpcBatch::new();
+        batch.add_get_metadata_request(None);
+
+        let metadata = metadata_from(single_response(self.execute("get_metadata", batch).await?)?)?;
+        self.record_latest_version(metadata.version);
+        Ok(metadata)
+    }
+
+    /// Fetches the latest metadata, the genesis transaction, the latest
+    /// transaction and the peer count, from the same ledger version, in one
+    /// round trip unless the ledger moved far since the last read
+    ///
+    /// NOTE: fullnodes at different heights disagree on the latest state, so
+    /// this isn't cross-checked
+    pub async fn get_ledger_status(&self) -> Result<LedgerStatus, DiemError> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_metadata_request(None);
+        batch.add_get_transactions_request(0, 1, false);
+        batch.add_get_transactions_request(self.latest_version(), LATEST_TRANSACTIONS_LIMIT, false);
         batch.add_get_network_status_request();
 
-        let mut result = self.client.execute(batch).await?;
+        let (endpoint, responses) = self.execute_retrying("get_ledger_status", batch).await;
+        let mut responses = batch_responses(responses?, 4)?.into_iter();
+        let metadata = metadata_from(responses.next().unwrap())?;
+        self.record_latest_version(metadata.version);
+        let genesis_transaction = transactions_from(responses.next().unwrap())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DiemError::unexpected_response("genesis transaction", "none"))?;
+        let transactions = transactions_from(responses.next().unwrap())?;
+        let peer_count = peer_count_from(responses.next().unwrap())?;
+        let latest_transaction = self
+            .find_latest_transaction(endpoint, "get_ledger_status", &metadata, transactions)
+            .await?;
 
-        if result.len() != 1 {
-            return Err(DiemError::unexpected_response(
-                "1 result",
-                format!("{} results", result.len()),
-            ));
-        }
+        Ok(LedgerStatus {
+            metadata,
+            genesis_transaction,
+            latest_transaction,
+            peer_count,
+        })
+    }
 
-        let result = result.remove(0)?;
-        match result {
-            JsonRpcResponse::NetworkStatusResponse(peer_count) => peer_count
-                .as_u64()
-                .ok_or_else(|| DiemError::unexpected_response("u64", "non-u64 number")),
-            _ => Err(DiemError::unexpected_response(
-                "NetworkStatusResponse",
-                "other",
-            )),
-        }
+    /// Fetches an account along with the latest transaction, from the same
+    /// ledger version, so that the account state is the one right after that
+    /// transaction. This is one round trip unless the ledger moved far since
+    /// the last read.
+    ///
+    /// NOTE: accounts can't be read at a given version, so the transaction is
+    /// looked for from the latest version seen instead
+    ///
+    /// NOTE: fullnodes at different heights disagree on the latest state, so
+    /// this isn't cross-checked
+    pub async fn get_account_snapshot(&self, address: &str) -> Result<AccountSnapshot, DiemError> {
+        let account_address = AccountAddress::from_str(address)?;
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_account_request(account_address);
+        batch.add_get_metadata_request(None);
+        batch.add_get_transactions_request(self.latest_version(), LATEST_TRANSACTIONS_LIMIT, false);
+
+        let (endpoint, responses) = self.execute_retrying("get_account_snapshot", batch).await;
+        let mut responses = batch_responses(responses?, 3)?.into_iter();
+        let account = account_from(responses.next().unwrap())?;
+        let metadata = metadata_from(responses.next().unwrap())?;
+        self.record_latest_version(metadata.version);
+        let transactions = transactions_from(responses.next().unwrap())?;
+        let latest_transaction = self
+            .find_latest_transaction(endpoint, "get_account_snapshot", &metadata, transactions)
+            .await?;
+
+        Ok(AccountSnapshot {
+            account,
+            latest_transaction,
+        })
     }
 
     pub async fn get_account_with_metadata(
@@ -141,30 +630,50 @@ impl Diem {
         batch.add_get_account_request(account_address);
         batch.add_get_metadata_request(None);
 
-        let mut result = self.client.execute(batch).await?;
+        let result = self.execute("get_account_with_metadata", batch).await?;
+        let mut responses = batch_responses(result, 2)?.into_iter();
+        let account = account_from(responses.next().unwrap())?;
+        let metadata = metadata_from(responses.next().unwrap())?;
+        self.record_latest_version(metadata.version);
 
-        if result.len() != 2 {
-            return Err(DiemError::unexpected_response(
-                "2 results",
-                format!("{} results", result.len()),
-            ));
-        }
+        Ok((account, metadata))
+    }
+
+    pub async fn get_account_transaction(
+        &self,
+        address: AccountAddress,
+        sequence_number: u64,
+        include_events: bool,
+    ) -> Result<Option<TransactionView>, DiemError> {
+        let mut batch = JsonRpcBatch::new();
+        batch.add_get_account_transaction_request(address, sequence_number, include_events);
 
-        let account_result = result.remove(0)?;
-        let metadata_result = result.remove(0)?;
+        let result = self.execute("get_account_transaction", batch).await?;
+        account_transaction_from(single_response(result)?)
+    }
 
-        if let (
-            JsonRpcResponse::AccountResponse(account),
-            JsonRpcResponse::MetadataViewResponse(metadata),
-        ) = (account_result, metadata_result)
-        {
-            Ok((account, metadata))
-        } else {
-            Err(DiemError::unexpected_response(
-                "(AccountResponse, MetadataViewResponse)",
-                "other",
-            ))
+    /// Fetches several accounts along with the metadata, from the same ledger version
+    pub async fn get_accounts_with_metadata(
+        &self,
+        addresses: &[&str],
+    ) -> Result<(Vec<Option<AccountView>>, MetadataView), DiemError> {
+        let mut batch = JsonRpcBatch::new();
+        for address in addresses {
+            let account_address = AccountAddress::from_str(address)?;
+            batch.add_get_account_request(account_address);
         }
+        batch.add_get_metadata_request(None);
+
+        let result = self.execute("get_accounts_with_metadata", batch).await?;
+        let mut responses = batch_responses(result, addresses.len() + 1)?;
+        let metadata = metadata_from(responses.pop().unwrap())?;
+        self.record_latest_version(metadata.version);
+        let accounts = responses
+            .into_iter()
+            .map(account_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok((accounts, metadata))
     }
 
     pub async fn submit(&self, transaction: &SignedTransaction) -> Result<(), DiemError> {
@@ -173,7 +682,11 @@ impl Diem {
             .add_submit_request(transaction.clone())
             .expect("shouldn't fail to serialize a constructed type");
 
-        let mut result = self.client.execute(batch).await?;
+        // NOTE: a failed submission is never retried, since the node may
+        // have accepted it anyway. The client can resubmit the same signed
+        // transaction, which is deduplicated by /construction/submit.
+        let endpoint = self.healthiest_endpoint(&[]);
+        let mut result = self.execute_on(endpoint, "submit", batch).await?;
 
         if result.len() != 1 {
             return Err(DiemError::unexpected_response(
@@ -182,7 +695,13 @@ impl Diem {
             ));
         }
 
-        let result = result.remove(0)?;
+        let result =
+            result.remove(0).map_err(|error| {
+                match SubmissionError::from_json_rpc_error(&error) {
+                    Some(submission_error) => DiemError::SubmissionRejected(submission_error),
+                    None => error.into(),
+                }
+            })?;
         if matches!(result, JsonRpcResponse::SubmissionResponse) {
             Ok(())
         } else {
@@ -194,6 +713,127 @@ impl Diem {
     }
 }
 
+/// Checks the number of responses to a batch, and unwraps them
+fn batch_responses(
+    result: Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>,
+    expected: usize,
+) -> Result<Vec<JsonRpcResponse>, DiemError> {
+    if result.len() != expected {
+        return Err(DiemError::unexpected_response(
+            format!("{} results", expected),
+            format!("{} results", result.len()),
+        ));
+    }
+
+    let mut responses = Vec::with_capacity(expected);
+    for response in result {
+        responses.push(response?);
+    }
+    Ok(responses)
+}
+
+fn single_response(
+    result: Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>,
+) -> Result<JsonRpcResponse, DiemError> {
+    Ok(batch_responses(result, 1)?.remove(0))
+}
+
+fn metadata_from(response: JsonRpcResponse) -> Result<MetadataView, DiemError> {
+    match response {
+        JsonRpcResponse::MetadataViewResponse(metadata) => Ok(metadata),
+        _ => Err(DiemError::unexpected_response(
+            "MetadataViewResponse",
+            "other",
+        )),
+    }
+}
+
+fn transactions_from(response: JsonRpcResponse) -> Result<Vec<TransactionView>, DiemError> {
+    match response {
+        JsonRpcResponse::TransactionsResponse(views) => Ok(views),
+        _ => Err(DiemError::unexpected_response(
+            "TransactionsResponse",
+            "other",
+        )),
+    }
+}
+
+fn metadata_with_transactions_from(
+    result: Vec<Result<JsonRpcResponse, JsonRpcAsyncClientError>>,
+) -> Result<(MetadataView, Vec<TransactionView>), DiemError> {
+    let mut responses = batch_responses(result, 2)?.into_iter();
+    let metadata = metadata_from(responses.next().unwrap())?;
+    let transactions = transactions_from(responses.next().unwrap())?;
+    Ok((metadata, transactions))
+}
+
+fn account_from(response: JsonRpcResponse) -> Result<Option<AccountView>, DiemError> {
+    match response {
+        JsonRpcResponse::AccountResponse(account) => Ok(account),
+        _ => Err(DiemError::unexpected_response("AccountResponse", "other")),
+    }
+}
+
+fn account_transaction_from(
+    response: JsonRpcResponse,
+) -> Result<Option<TransactionView>, DiemError> {
+    match response {
+        JsonRpcResponse::AccountTransactionResponse(view) => Ok(view),
+        _ => Err(DiemError::unexpected_response(
+            "AccountTransactionResponse",
+            "other",
+        )),
+    }
+}
+
+fn peer_count_from(response: JsonRpcResponse) -> Result<u64, DiemError> {
+    match response {
+        JsonRpcResponse::NetworkStatusResponse(peer_count) => peer_count
+            .as_u64()
+            .ok_or_else(|| DiemError::unexpected_response("u64", "non-u64 number")),
+        _ => Err(DiemError::unexpected_response(
+            "NetworkStatusResponse",
+            "other",
+        )),
+    }
+}
+
+fn compare_metadata(
+    metadata: &MetadataView,
+    other_metadata: &MetadataView,
+    endpoint: &Endpoint,
+    other: &Endpoint,
+) -> Result<(), DiemError> {
+    if other_metadata.accumulator_root_hash != metadata.accumulator_root_hash {
+        return Err(ledger_mismatch(format!(
+            "accumulator root at version {} differs between {} and {}",
+            metadata.version, endpoint.url, other.url
+        )));
+    }
+    Ok(())
+}
+
+/// A fullnode lagging behind returns fewer transactions, so only the ones
+/// both fullnodes have are compared
+fn compare_transactions(
+    transactions: &[TransactionView],
+    other_transactions: &[TransactionView],
+    endpoint: &Endpoint,
+    other: &Endpoint,
+) -> Result<(), DiemError> {
+    for (transaction, other_transaction) in transactions.iter().zip(other_transactions) {
+        if transaction.version != other_transaction.version
+            || transaction.hash != other_transaction.hash
+        {
+            return Err(ledger_mismatch(format!(
+                "transaction at version {} differs between {} and {}",
+                transaction.version, endpoint.url, other.url
+            )));
+        }
+    }
+    Ok(())
+}
+
 pub fn vmstatus_to_str(vm_status: &VMStatusView) -> &'static str {
     match vm_status {
         VMStatusView::Executed => "executed",
@@ -1,5 +1,5 @@
 use crate::{
-    diem,
+    diem::{self, SubmissionError},
     types::{self, ErrorDetails},
 };
 use thiserror::Error;
@@ -10,7 +10,7 @@ pub enum ApiError {
     #[error("bad block request")]
     BadBlockRequest,
     #[error("diem error: {0:?}")]
-    DiemError(#[from] diem::DiemError),
+    DiemError(diem::DiemError),
     #[error("bad network")]
     BadNetwork,
     #[error("deserialization failed: {0}")]
@@ -39,6 +39,36 @@ pub enum ApiError {
     BadSignatureCount,
     #[error("historic balances unsupported")]
     HistoricBalancesUnsupported,
+    #[error("insufficient balance")]
+    InsufficientBalance,
+    #[error("receiver not found")]
+    ReceiverNotFound,
+    #[error("account frozen")]
+    AccountFrozen,
+    #[error("receiver missing currency")]
+    ReceiverMissingCurrency,
+    #[error("chain id mismatch")]
+    ChainIdMismatch,
+    #[error("transaction expired")]
+    TransactionExpired,
+    #[error("sequence number too old")]
+    SequenceNumberTooOld,
+    #[error("sequence number too new")]
+    SequenceNumberTooNew,
+    #[error("insufficient balance for gas")]
+    InsufficientBalanceForGas,
+    #[error("invalid auth key")]
+    InvalidAuthKey,
+    #[error("mempool full")]
+    MempoolFull,
+    #[error("too many pending transactions")]
+    TooManyPendingTransactions,
+    #[error("transaction already pending")]
+    TransactionAlreadyPending,
+    #[error("auth key mismatch")]
+    AuthKeyMismatch,
+    #[error("ledger mismatch: {0}")]
+    LedgerMismatch(String),
 }
 
 impl ApiError {
@@ -60,6 +90,21 @@ impl ApiError {
             ApiError::BadCoin => 150,
             ApiError::BadSignatureCount => 160,
             ApiError::HistoricBalancesUnsupported => 170,
+            ApiError::InsufficientBalance => 180,
+            ApiError::ReceiverNotFound => 190,
+            ApiError::AccountFrozen => 200,
+            ApiError::ReceiverMissingCurrency => 210,
+            ApiError::ChainIdMismatch => 220,
+            ApiError::TransactionExpired => 230,
+            ApiError::SequenceNumberTooOld => 240,
+            ApiError::SequenceNumberTooNew => 250,
+            ApiError::InsufficientBalanceForGas => 260,
+            ApiError::InvalidAuthKey => 270,
+            ApiError::MempoolFull => 280,
+            ApiError::TooManyPendingTransactions => 290,
+            ApiError::TransactionAlreadyPending => 300,
+            ApiError::AuthKeyMismatch => 310,
+            ApiError::LedgerMismatch(_) => 320,
         }
     }
 
@@ -81,6 +126,21 @@ impl ApiError {
             ApiError::BadCoin => false,
             ApiError::BadSignatureCount => false,
             ApiError::HistoricBalancesUnsupported => false,
+            ApiError::InsufficientBalance => false,
+            ApiError::ReceiverNotFound => false,
+            ApiError::AccountFrozen => false,
+            ApiError::ReceiverMissingCurrency => false,
+            ApiError::ChainIdMismatch => false,
+            ApiError::TransactionExpired => false,
+            ApiError::SequenceNumberTooOld => false,
+            ApiError::SequenceNumberTooNew => true,
+            ApiError::InsufficientBalanceForGas => false,
+            ApiError::InvalidAuthKey => false,
+            ApiError::MempoolFull => true,
+            ApiError::TooManyPendingTransactions => true,
+            ApiError::TransactionAlreadyPending => false,
+            ApiError::AuthKeyMismatch => false,
+            ApiError::LedgerMismatch(_) => true,
         }
     }
 
@@ -102,6 +162,21 @@ impl ApiError {
             ApiError::BadCoin => StatusCode::BAD_REQUEST,
             ApiError::BadSignatureCount => StatusCode::BAD_REQUEST,
             ApiError::HistoricBalancesUnsupported => StatusCode::BAD_REQUEST,
+            ApiError::InsufficientBalance => StatusCode::BAD_REQUEST,
+            ApiError::ReceiverNotFound => StatusCode::NOT_FOUND,
+            ApiError::AccountFrozen => StatusCode::BAD_REQUEST,
+            ApiError::ReceiverMissingCurrency => StatusCode::BAD_REQUEST,
+            ApiError::ChainIdMismatch => StatusCode::BAD_REQUEST,
+            ApiError::TransactionExpired => StatusCode::BAD_REQUEST,
+            ApiError::SequenceNumberTooOld => StatusCode::BAD_REQUEST,
+            ApiError::SequenceNumberTooNew => StatusCode::BAD_REQUEST,
+            ApiError::InsufficientBalanceForGas => StatusCode::BAD_REQUEST,
+            ApiError::InvalidAuthKey => StatusCode::BAD_REQUEST,
+            ApiError::MempoolFull => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::TooManyPendingTransactions => StatusCode::BAD_REQUEST,
+            ApiError::TransactionAlreadyPending => StatusCode::BAD_REQUEST,
+            ApiError::AuthKeyMismatch => StatusCode::BAD_REQUEST,
+            ApiError::LedgerMismatch(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
@@ -218,6 +293,96 @@ impl ApiError {
                 retriable: false,
                 details: None,
             },
+            types::Error {
+                message: "insufficient balance".to_string(),
+                code: 180,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "receiver not found".to_string(),
+                code: 190,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "account frozen".to_string(),
+                code: 200,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "receiver missing currency".to_string(),
+                code: 210,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "chain id mismatch".to_string(),
+                code: 220,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "transaction expired".to_string(),
+                code: 230,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "sequence number too old".to_string(),
+                code: 240,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "sequence number too new".to_string(),
+                code: 250,
+                retriable: true,
+                details: None,
+            },
+            types::Error {
+                message: "insufficient balance for gas".to_string(),
+                code: 260,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "invalid auth key".to_string(),
+                code: 270,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "mempool full".to_string(),
+                code: 280,
+                retriable: true,
+                details: None,
+            },
+            types::Error {
+                message: "too many pending transactions".to_string(),
+                code: 290,
+                retriable: true,
+                details: None,
+            },
+            types::Error {
+                message: "transaction already pending".to_string(),
+                code: 300,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "auth key mismatch".to_string(),
+                code: 310,
+                retriable: false,
+                details: None,
+            },
+            types::Error {
+                message: "ledger mismatch".to_string(),
+                code: 320,
+                retriable: true,
+                details: None,
+            },
         ]
     }
 
@@ -231,6 +396,33 @@ impl ApiError {
     }
 }
 
+impl std::convert::From<diem::DiemError> for ApiError {
+    fn from(diem_error: diem::DiemError) -> Self {
+        // submissions rejected by the node, and disagreeing fullnodes, map to
+        // their own errors
+        let submission_error = match diem_error {
+            diem::DiemError::SubmissionRejected(submission_error) => submission_error,
+            diem::DiemError::LedgerMismatch(details) => return ApiError::LedgerMismatch(details),
+            diem_error => return ApiError::DiemError(diem_error),
+        };
+
+        match submission_error {
+            SubmissionError::SequenceNumberTooOld => ApiError::SequenceNumberTooOld,
+            SubmissionError::SequenceNumberTooNew => ApiError::SequenceNumberTooNew,
+            SubmissionError::InsufficientBalanceForGas => ApiError::InsufficientBalanceForGas,
+            SubmissionError::InvalidAuthKey => ApiError::InvalidAuthKey,
+            SubmissionError::InvalidSignature => ApiError::BadSignature,
+            SubmissionError::BadChainId => ApiError::ChainIdMismatch,
+            SubmissionError::TransactionExpired => ApiError::TransactionExpired,
+            SubmissionError::SendingAccountDoesNotExist => ApiError::AccountNotFound,
+            SubmissionError::SendingAccountFrozen => ApiError::AccountFrozen,
+            SubmissionError::MempoolFull => ApiError::MempoolFull,
+            SubmissionError::TooManyPendingTransactions => ApiError::TooManyPendingTransactions,
+            SubmissionError::TransactionAlreadyPending => ApiError::TransactionAlreadyPending,
+        }
+    }
+}
+
 impl warp::reject::Reject for ApiError {}
 
 impl std::convert::From<ApiError> for warp::reject::Rejection {
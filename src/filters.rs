@@ -1,12 +1,18 @@
 use crate::{
+    cache::BlockCache,
+    diem::Diem,
     error::ApiError,
+    metrics,
     options::Options,
+    store::BlockStore,
 };
 use futures::future::BoxFuture;
 use serde::{Serialize, Deserialize};
 use std::{
     convert::Infallible,
     future::Future,
+    sync::Arc,
+    time::Instant,
 };
 use warp::Filter;
 
@@ -14,6 +20,18 @@ pub fn with_options(options: Options) -> impl Filter<Extract=(Options,), Error=I
     warp::any().map(move || options.clone())
 }
 
+pub fn with_diem(diem: Diem) -> impl Filter<Extract=(Diem,), Error=Infallible> + Clone {
+    warp::any().map(move || diem.clone())
+}
+
+pub fn with_block_cache(cache: Arc<BlockCache>) -> impl Filter<Extract=(Arc<BlockCache>,), Error=Infallible> + Clone {
+    warp::any().map(move || cache.clone())
+}
+
+pub fn with_block_store(store: Option<Arc<BlockStore>>) -> impl Filter<Extract=(Option<Arc<BlockStore>>,), Error=Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct EmptyRequest;
 
@@ -21,32 +39,73 @@ pub fn with_empty_request() -> impl Filter<Extract=(EmptyRequest,), Error=Infall
     warp::any().map(move || EmptyRequest)
 }
 
+/// Wraps a handler into a warp handler, recording metrics under `route`
 pub fn handle<'a, F, R, Req, Resp>(
+    route: &'static str,
     handler: F,
 ) -> impl Fn(Req, Options) -> BoxFuture<'static, Result<warp::reply::WithStatus<warp::reply::Json>, Infallible>>
        + Clone
 where
     F: FnOnce(Req, Options) -> R + Clone + Copy + Send + 'static,
-    R: Future<Output = Result<Resp, ApiError>> + Send,
+    R: Future<Output = Result<Resp, ApiError>> + Send + 'static,
+    Req: Deserialize<'a> + Send + 'static,
+    Resp: Serialize,
+{
+    move |request, options| Box::pin(reply(route, handler(request, options)))
+}
+
+/// Same as `handle`, for handlers that talk to the node
+pub fn handle_with_diem<'a, F, R, Req, Resp>(
+    route: &'static str,
+    handler: F,
+) -> impl Fn(Req, Options, Diem) -> BoxFuture<'static, Result<warp::reply::WithStatus<warp::reply::Json>, Infallible>>
+       + Clone
+where
+    F: FnOnce(Req, Options, Diem) -> R + Clone + Copy + Send + 'static,
+    R: Future<Output = Result<Resp, ApiError>> + Send + 'static,
     Req: Deserialize<'a> + Send + 'static,
     Resp: Serialize,
 {
-    move |request, options| {
-        let fut = async move {
-            match handler(request, options).await {
-                Ok(response) => Ok(warp::reply::with_status(
-                    warp::reply::json(&response),
-                    warp::http::StatusCode::OK,
-                )),
-                Err(api_error) => {
-                    let status = api_error.status_code();
-                    Ok(warp::reply::with_status(
-                        warp::reply::json(&api_error.into_error()),
-                        status,
-                    ))
-                }
-            }
-        };
-        Box::pin(fut)
+    move |request, options, diem| Box::pin(reply(route, handler(request, options, diem)))
+}
+
+/// Same as `handle_with_diem`, for handlers that also read cached and stored blocks
+pub fn handle_with_blocks<'a, F, R, Req, Resp>(
+    route: &'static str,
+    handler: F,
+) -> impl Fn(Req, Options, Diem, Arc<BlockCache>, Option<Arc<BlockStore>>) -> BoxFuture<'static, Result<warp::reply::WithStatus<warp::reply::Json>, Infallible>>
+       + Clone
+where
+    F: FnOnce(Req, Options, Diem, Arc<BlockCache>, Option<Arc<BlockStore>>) -> R + Clone + Copy + Send + 'static,
+    R: Future<Output = Result<Resp, ApiError>> + Send + 'static,
+    Req: Deserialize<'a> + Send + 'static,
+    Resp: Serialize,
+{
+    move |request, options, diem, cache, store| Box::pin(reply(route, handler(request, options, diem, cache, store)))
+}
+
+async fn reply<R, Resp>(
+    route: &'static str,
+    response: R,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible>
+where
+    R: Future<Output = Result<Resp, ApiError>>,
+    Resp: Serialize,
+{
+    let started = Instant::now();
+    let result = response.await;
+    metrics::record_request(route, started.elapsed(), result.as_ref().err().map(ApiError::code));
+    match result {
+        Ok(response) => Ok(warp::reply::with_status(
+            warp::reply::json(&response),
+            warp::http::StatusCode::OK,
+        )),
+        Err(api_error) => {
+            let status = api_error.status_code();
+            Ok(warp::reply::with_status(
+                warp::reply::json(&api_error.into_error()),
+                status,
+            ))
+        }
     }
 }
@@ -0,0 +1,70 @@
+use crate::{
+    diem::Diem,
+    filters::{with_diem, with_options},
+    options::Options,
+};
+use log::warn;
+use std::{
+    convert::Infallible,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use warp::{http::StatusCode, Filter};
+
+pub fn routes(
+    options: Options,
+    diem: Diem,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get().and(
+        warp::path!("healthz")
+            .map(|| warp::reply::with_status("ok".to_string(), StatusCode::OK))
+            .or(warp::path!("readyz")
+                .and(with_options(options))
+                .and(with_diem(diem))
+                .and_then(readyz)),
+    )
+}
+
+async fn readyz(options: Options, diem: Diem) -> Result<impl warp::Reply, Infallible> {
+    match check_ready(&options, &diem).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            "ready".to_string(),
+            StatusCode::OK,
+        )),
+        Err(reason) => {
+            warn!("not ready: {}", reason);
+            Ok(warp::reply::with_status(
+                reason,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ))
+        }
+    }
+}
+
+/// The proxy is ready when its fullnode is on the configured network, and
+/// its ledger is close enough to the clock
+async fn check_ready(options: &Options, diem: &Diem) -> Result<(), String> {
+    let metadata = diem
+        .get_metadata()
+        .await
+        .map_err(|e| format!("failed to get the ledger metadata: {}", e))?;
+
+    if let Some(chain_id) = options.chain_id() {
+        if metadata.chain_id != chain_id.id() {
+            return Err(format!(
+                "chain id {} doesn't match network {}",
+                metadata.chain_id, options.network
+            ));
+        }
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+    let ledger_time = Duration::from_micros(metadata.timestamp);
+    let lag = now.checked_sub(ledger_time).unwrap_or_default();
+    if lag > Duration::from_secs(options.max_ledger_lag_secs) {
+        return Err(format!("ledger lags {}s behind the clock", lag.as_secs()));
+    }
+
+    Ok(())
+}
@@ -1,19 +1,26 @@
 use crate::error::ApiError;
 use log::{error, info};
 use options::Options;
-use std::convert::Infallible;
+use std::{convert::Infallible, sync::Arc};
 use structopt::StructOpt;
 use warp::{http::StatusCode, Filter};
 
 mod account;
 mod block;
+mod cache;
 mod construction;
 mod consts;
 mod diem;
 mod error;
 mod filters;
+mod health;
+mod metrics;
 mod network;
+mod operations;
 mod options;
+mod pending;
+mod prefetch;
+mod store;
 mod types;
 
 async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
@@ -66,13 +73,33 @@ async fn main() {
     env_logger::init();
 
     let options = Options::from_args();
+    let diem = diem::Diem::new(&options).expect("failed to create the diem client");
+    let block_store = options.block_store_path.as_ref().map(|path| {
+        let store = store::BlockStore::open(path, options.block_store_max_bytes)
+            .expect("failed to open the block store");
+        Arc::new(store)
+    });
+    let block_cache = Arc::new(cache::BlockCache::new(
+        options.block_cache_size,
+        options.block_cache_max_bytes,
+    ));
 
-    let routes = network::routes(options.clone())
-        .or(block::routes(options.clone()))
-        .or(account::routes(options.clone()))
-        .or(construction::routes(options.clone()))
+    let routes = network::routes(options.clone(), diem.clone())
+        .or(block::routes(options.clone(), diem.clone(), block_cache, block_store))
+        .or(account::routes(options.clone(), diem.clone()))
+        .or(construction::routes(options.clone(), diem.clone()))
+        .or(health::routes(options.clone(), diem.clone()))
         .recover(handle_rejection);
 
+    // metrics are kept off the Rosetta port, so they aren't exposed along with it
+    if let Some(port) = options.metrics_port {
+        let (address, server) = warp::serve(metrics::routes())
+            .try_bind_ephemeral(([0, 0, 0, 0], port))
+            .expect("failed to bind the metrics port");
+        info!("serving metrics on {}", address);
+        tokio::spawn(server);
+    }
+
     info!("listening on 0.0.0.0:3030");
     warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
 }
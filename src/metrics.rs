@@ -0,0 +1,120 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use std::time::Duration;
+use warp::Filter;
+
+static REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rosetta_requests_total",
+        "Rosetta requests, by route and error code (0 for successes)",
+        &["route", "code"]
+    )
+    .unwrap()
+});
+
+static REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "rosetta_request_duration_seconds",
+        "Time to handle a Rosetta request, by route",
+        &["route"]
+    )
+    .unwrap()
+});
+
+static JSON_RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "diem_json_rpc_duration_seconds",
+        "Time of a JSON-RPC round trip to a fullnode, by method and result",
+        &["method", "result"]
+    )
+    .unwrap()
+});
+
+static CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "block_cache_lookups_total",
+        "Block lookups in the memory cache and the on-disk store, by result",
+        &["cache", "result"]
+    )
+    .unwrap()
+});
+
+static LATEST_TRANSACTION_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "latest_transaction_lookups_total",
+        "Reads of the latest transaction, by whether it was found in the batch of the latest \
+         metadata (`batch`) or fetched with a second request (`follow_up`)",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static LEDGER_VERSION: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "diem_ledger_version",
+        "The latest ledger version read from a fullnode"
+    )
+    .unwrap()
+});
+
+static SUBMISSIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "submitted_transactions_total",
+        "Transactions submitted through /construction/submit, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub fn record_request(route: &str, duration: Duration, error_code: Option<u64>) {
+    let code = error_code.unwrap_or(0).to_string();
+    REQUESTS.with_label_values(&[route, &code]).inc();
+    REQUEST_DURATION
+        .with_label_values(&[route])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn record_json_rpc(method: &str, duration: Duration, succeeded: bool) {
+    let result = if succeeded { "success" } else { "failure" };
+    JSON_RPC_DURATION
+        .with_label_values(&[method, result])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn record_cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[cache, result]).inc();
+}
+
+pub fn record_latest_transaction_lookup(in_batch: bool) {
+    let result = if in_batch { "batch" } else { "follow_up" };
+    LATEST_TRANSACTION_LOOKUPS
+        .with_label_values(&[result])
+        .inc();
+}
+
+pub fn set_ledger_version(version: u64) {
+    LEDGER_VERSION.set(version as i64);
+}
+
+/// Counts a submission as `accepted`, `rejected` (by the proxy or the node)
+/// or `duplicate`
+pub fn record_submission(result: &str) {
+    SUBMISSIONS.with_label_values(&[result]).inc();
+}
+
+pub fn routes() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::get().and(warp::path!("metrics")).map(|| {
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        encoder
+            .encode(&prometheus::gather(), &mut buffer)
+            .expect("shouldn't fail to encode metrics into a buffer");
+        warp::http::Response::builder()
+            .header("content-type", encoder.format_type())
+            .body(buffer)
+    })
+}
@@ -1,8 +1,10 @@
 use crate::{
     consts,
-    diem::{self, Diem},
+    diem::{self, Diem, LedgerStatus},
     error::ApiError,
-    filters::{handle, with_empty_request, with_options, EmptyRequest},
+    filters::{
+        handle, handle_with_diem, with_diem, with_empty_request, with_options, EmptyRequest,
+    },
     options::Options,
     types::{
         Allow, BlockIdentifier, NetworkIdentifier, NetworkListResponse, NetworkOptionsResponse,
@@ -14,22 +16,24 @@ use warp::Filter;
 
 pub fn routes(
     options: Options,
+    diem: Diem,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::post()
         .and(
             warp::path!("network" / "list")
                 .and(with_empty_request())
                 .and(with_options(options.clone()))
-                .and_then(handle(network_list)),
+                .and_then(handle("/network/list", network_list)),
         )
         .or(warp::path!("network" / "options")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(network_options)))
+            .and_then(handle("/network/options", network_options)))
         .or(warp::path!("network" / "status")
             .and(warp::body::json())
             .and(with_options(options.clone()))
-            .and_then(handle(network_status)))
+            .and(with_diem(diem.clone()))
+            .and_then(handle_with_diem("/network/status", network_status)))
 }
 
 async fn network_list(
@@ -85,6 +89,9 @@ async fn network_options(
         "newepoch".to_string(),
         "newblock".to_string(),
         "createaccount".to_string(),
+        "rotateauthkey".to_string(),
+        "addcurrency".to_string(),
+        "script".to_string(),
         "unknown".to_string(),
         "sentfee".to_string(), // NOTE: not from diem events, since tx fees aren't events
         "receivedfee".to_string(), // NOTE: not from diem events, since tx fees aren't events
@@ -110,6 +117,7 @@ async fn network_options(
 async fn network_status(
     network_request: NetworkRequest,
     options: Options,
+    diem: Diem,
 ) -> Result<NetworkStatusResponse, ApiError> {
     debug!("/network/status");
     if network_request.network_identifier.blockchain != consts::BLOCKCHAIN
@@ -118,24 +126,24 @@ async fn network_status(
         return Err(ApiError::BadNetwork);
     }
 
-    let diem = Diem::new(&options.diem_endpoint);
-    let metadata = diem.get_metadata(None).await?;
-
-    let genesis_tx = diem.get_transactions(0, 1, false).await?;
-    let latest_tx = diem.get_transactions(metadata.version, 1, false).await?;
-    let num_peers = diem.get_network_status().await?;
+    let LedgerStatus {
+        metadata,
+        genesis_transaction: genesis_tx,
+        latest_transaction: latest_tx,
+        peer_count: num_peers,
+    } = diem.get_ledger_status().await?;
 
     let genesis_block_identifier = BlockIdentifier {
-        index: genesis_tx[0].version,
-        hash: genesis_tx[0].hash.clone().to_string(),
+        index: genesis_tx.version,
+        hash: genesis_tx.hash.clone().to_string(),
     };
 
     // note: diem timestamps are in microseconds, so we convert to milliseconds
     let current_block_timestamp = metadata.timestamp / 1000;
 
     let current_block_identifier = BlockIdentifier {
-        index: latest_tx[0].version,
-        hash: latest_tx[0].hash.clone().to_string(),
+        index: latest_tx.version,
+        hash: latest_tx.hash.clone().to_string(),
     };
 
     let peers: Vec<Peer> = (0..num_peers)
@@ -0,0 +1,1373 @@
+use crate::{
+    error::ApiError,
+    types::{
+        AccountIdentifier, Amount, Currency, Operation, OperationIdentifier, OperationMetadata,
+        ScriptMetadata, SubAccountIdentifier,
+    },
+};
+use anyhow::anyhow;
+use compiled_stdlib::transaction_scripts::StdlibScript;
+use diem_types::transaction::{
+    metadata::{
+        GeneralMetadata, GeneralMetadataV0, Metadata, TravelRuleMetadata, TravelRuleMetadataV0,
+    },
+    Script, TransactionArgument,
+};
+use move_core_types::{
+    account_address::AccountAddress,
+    identifier::Identifier,
+    language_storage::{StructTag, TypeTag, CORE_CODE_ADDRESS},
+};
+use std::{convert::TryFrom, str::FromStr};
+use transaction_builder_generated::stdlib::{self, ScriptCall};
+
+/// Length of the authentication key prefix required to create an account
+const AUTH_KEY_PREFIX_LENGTH: usize = 16;
+
+/// Length of an authentication key
+const AUTH_KEY_LENGTH: usize = 32;
+
+/// Gas currency for transactions that don't move funds, unless one is given
+const DEFAULT_GAS_CURRENCY: &str = "XUS";
+
+/// Length of a subaddress identifying a user of a VASP
+const SUBADDRESS_LENGTH: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+enum Value {
+    Credit(u64),
+    Debit(u64),
+}
+
+impl Value {
+    fn reconciles(&self, value: Value) -> bool {
+        match (*self, value) {
+            (Value::Credit(c), Value::Debit(d)) => c == d,
+            (Value::Debit(d), Value::Credit(c)) => d == c,
+            _ => false,
+        }
+    }
+
+    fn amount(self) -> u64 {
+        match self {
+            Value::Credit(v) => v,
+            Value::Debit(v) => v,
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(anyhow!("empty input"));
+        }
+
+        let v = s.parse::<i128>()?;
+        match v < 0 {
+            true => Ok(Value::Debit((-v) as u64)),
+            false => Ok(Value::Credit(v as u64)),
+        }
+    }
+}
+
+pub struct Transfer {
+    pub sender: AccountAddress,
+    pub receiver: AccountAddress,
+    pub amount: u64,
+    pub currency: String,
+    pub from_subaddress: Option<Vec<u8>>,
+    pub to_subaddress: Option<Vec<u8>>,
+    /// Off-chain reference id of a travel-rule exchange between the VASPs
+    pub travel_rule_metadata: Option<String>,
+    /// Compliance signature of the receiving VASP over the travel-rule metadata
+    pub metadata_signature: Vec<u8>,
+}
+
+impl Transfer {
+    /// Payment metadata carrying the subaddresses or the travel-rule
+    /// reference id, if there are any
+    fn metadata(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let has_subaddresses = self.from_subaddress.is_some() || self.to_subaddress.is_some();
+
+        let metadata = match (&self.travel_rule_metadata, has_subaddresses) {
+            (Some(_), true) => {
+                return Err(anyhow!("travel rule metadata can't carry subaddresses"));
+            }
+            (Some(reference_id), false) => Metadata::TravelRuleMetadata(
+                TravelRuleMetadata::TravelRuleMetadataVersion0(TravelRuleMetadataV0 {
+                    off_chain_reference_id: Some(reference_id.clone()),
+                }),
+            ),
+            (None, true) => Metadata::GeneralMetadata(GeneralMetadata::GeneralMetadataVersion0(
+                GeneralMetadataV0 {
+                    to_subaddress: self.to_subaddress.clone(),
+                    from_subaddress: self.from_subaddress.clone(),
+                    referenced_event: None,
+                },
+            )),
+            (None, false) => return Ok(vec![]),
+        };
+
+        Ok(bcs::to_bytes(&metadata)?)
+    }
+
+    /// The attestation as operation metadata, shown on the sent payment
+    fn attestation(&self) -> Option<OperationMetadata> {
+        if self.travel_rule_metadata.is_none() && self.metadata_signature.is_empty() {
+            return None;
+        }
+
+        let metadata_signature = if self.metadata_signature.is_empty() {
+            None
+        } else {
+            Some(hex::encode(&self.metadata_signature))
+        };
+
+        Some(OperationMetadata {
+            travel_rule_metadata: self.travel_rule_metadata.clone(),
+            metadata_signature,
+            ..OperationMetadata::default()
+        })
+    }
+
+    /// Uses the attestation given as construction metadata, unless the
+    /// operations already carry one
+    pub fn set_default_attestation(
+        &mut self,
+        travel_rule_metadata: Option<String>,
+        metadata_signature: Vec<u8>,
+    ) {
+        if self.travel_rule_metadata.is_none() {
+            self.travel_rule_metadata = travel_rule_metadata;
+        }
+        if self.metadata_signature.is_empty() {
+            self.metadata_signature = metadata_signature;
+        }
+    }
+}
+
+pub struct CreateAccount {
+    pub sender: AccountAddress,
+    pub address: AccountAddress,
+    pub auth_key_prefix: Vec<u8>,
+    pub add_all_currencies: bool,
+    pub initial_balance: u64,
+    pub currency: String,
+}
+
+pub struct RotateAuthKey {
+    pub sender: AccountAddress,
+    pub new_key: Vec<u8>,
+    /// Rotations authorized by a sliding nonce use the with-nonce script
+    pub sliding_nonce: Option<u64>,
+    pub gas_currency: String,
+}
+
+pub struct AddCurrency {
+    pub sender: AccountAddress,
+    pub currency: String,
+    /// A currency the account already holds, to pay for gas
+    pub gas_currency: String,
+}
+
+/// Tiered mint by treasury compliance to a designated dealer
+pub struct Mint {
+    pub sender: AccountAddress,
+    pub designated_dealer: AccountAddress,
+    pub amount: u64,
+    pub currency: String,
+    pub sliding_nonce: u64,
+    pub tier_index: u64,
+    pub gas_currency: String,
+}
+
+/// Moves funds of a designated dealer to its preburn area
+pub struct Preburn {
+    pub sender: AccountAddress,
+    pub amount: u64,
+    pub currency: String,
+}
+
+/// Burn by treasury compliance of the oldest preburn request of an account
+pub struct Burn {
+    pub sender: AccountAddress,
+    pub preburn_address: AccountAddress,
+    pub currency: String,
+    pub sliding_nonce: u64,
+    pub gas_currency: String,
+}
+
+/// Returns the oldest preburn request of an account to its balance
+pub struct CancelBurn {
+    pub sender: AccountAddress,
+    pub preburn_address: AccountAddress,
+    pub currency: String,
+    pub gas_currency: String,
+}
+
+/// Any other stdlib script, which can be decoded but not constructed
+pub struct GenericScript {
+    pub sender: AccountAddress,
+    pub script: Script,
+    pub gas_currency: String,
+}
+
+/// A transaction that can be built or decoded through the construction API
+pub enum Intent {
+    Transfer(Transfer),
+    CreateAccount(CreateAccount),
+    RotateAuthKey(RotateAuthKey),
+    AddCurrency(AddCurrency),
+    Mint(Mint),
+    Preburn(Preburn),
+    Burn(Burn),
+    CancelBurn(CancelBurn),
+    Script(GenericScript),
+}
+
+impl Intent {
+    pub fn from_operations(operations: &[Operation]) -> Result<Intent, anyhow::Error> {
+        match operations.first().map(|op| op.type_.as_ref()) {
+            Some("sentpayment") | Some("receivedpayment") => {
+                extract_transfer_from_operations(operations).map(Intent::Transfer)
+            }
+            Some("createaccount") => {
+                extract_create_account_from_operations(operations).map(Intent::CreateAccount)
+            }
+            Some("rotateauthkey") => {
+                extract_rotate_auth_key_from_operations(operations).map(Intent::RotateAuthKey)
+            }
+            Some("addcurrency") => {
+                extract_add_currency_from_operations(operations).map(Intent::AddCurrency)
+            }
+            Some("mint") => extract_mint_from_operations(operations).map(Intent::Mint),
+            Some("preburn") => extract_preburn_from_operations(operations).map(Intent::Preburn),
+            Some("burn") => extract_burn_from_operations(operations).map(Intent::Burn),
+            Some("cancelburn") => {
+                extract_cancel_burn_from_operations(operations).map(Intent::CancelBurn)
+            }
+            _ => Err(anyhow!("unsupported operations")),
+        }
+    }
+
+    /// Decodes a script into the intent that built it
+    pub fn from_script(
+        sender: AccountAddress,
+        gas_currency: &str,
+        script: &Script,
+    ) -> Result<Intent, ApiError> {
+        match ScriptCall::decode(script) {
+            Some(ScriptCall::PeerToPeerWithMetadata {
+                currency,
+                payee,
+                amount,
+                metadata,
+                metadata_signature,
+            }) => {
+                let metadata = decode_payment_metadata(&metadata)?;
+                Ok(Intent::Transfer(Transfer {
+                    sender,
+                    receiver: payee,
+                    amount,
+                    currency: currency_code(&currency)?,
+                    from_subaddress: metadata.from_subaddress,
+                    to_subaddress: metadata.to_subaddress,
+                    travel_rule_metadata: metadata.travel_rule_metadata,
+                    metadata_signature,
+                }))
+            }
+            Some(ScriptCall::CreateChildVaspAccount {
+                coin_type,
+                child_address,
+                auth_key_prefix,
+                add_all_currencies,
+                child_initial_balance,
+            }) => Ok(Intent::CreateAccount(CreateAccount {
+                sender,
+                address: child_address,
+                auth_key_prefix,
+                add_all_currencies,
+                initial_balance: child_initial_balance,
+                currency: currency_code(&coin_type)?,
+            })),
+            Some(ScriptCall::RotateAuthenticationKey { new_key }) => {
+                Ok(Intent::RotateAuthKey(RotateAuthKey {
+                    sender,
+                    new_key,
+                    sliding_nonce: None,
+                    gas_currency: gas_currency.to_string(),
+                }))
+            }
+            Some(ScriptCall::RotateAuthenticationKeyWithNonce {
+                sliding_nonce,
+                new_key,
+            }) => Ok(Intent::RotateAuthKey(RotateAuthKey {
+                sender,
+                new_key,
+                sliding_nonce: Some(sliding_nonce),
+                gas_currency: gas_currency.to_string(),
+            })),
+            Some(ScriptCall::AddCurrencyToAccount { currency }) => {
+                Ok(Intent::AddCurrency(AddCurrency {
+                    sender,
+                    currency: currency_code(&currency)?,
+                    gas_currency: gas_currency.to_string(),
+                }))
+            }
+            Some(ScriptCall::TieredMint {
+                coin_type,
+                sliding_nonce,
+                designated_dealer_address,
+                mint_amount,
+                tier_index,
+            }) => Ok(Intent::Mint(Mint {
+                sender,
+                designated_dealer: designated_dealer_address,
+                amount: mint_amount,
+                currency: currency_code(&coin_type)?,
+                sliding_nonce,
+                tier_index,
+                gas_currency: gas_currency.to_string(),
+            })),
+            Some(ScriptCall::Preburn { token, amount }) => Ok(Intent::Preburn(Preburn {
+                sender,
+                amount,
+                currency: currency_code(&token)?,
+            })),
+            Some(ScriptCall::Burn {
+                token,
+                sliding_nonce,
+                preburn_address,
+            }) => Ok(Intent::Burn(Burn {
+                sender,
+                preburn_address,
+                currency: currency_code(&token)?,
+                sliding_nonce,
+                gas_currency: gas_currency.to_string(),
+            })),
+            Some(ScriptCall::CancelBurn {
+                token,
+                preburn_address,
+            }) => Ok(Intent::CancelBurn(CancelBurn {
+                sender,
+                preburn_address,
+                currency: currency_code(&token)?,
+                gas_currency: gas_currency.to_string(),
+            })),
+            Some(_) => Ok(Intent::Script(GenericScript {
+                sender,
+                script: script.clone(),
+                gas_currency: gas_currency.to_string(),
+            })),
+            None => Err(ApiError::BadTransactionScript),
+        }
+    }
+
+    pub fn sender(&self) -> AccountAddress {
+        match self {
+            Intent::Transfer(transfer) => transfer.sender,
+            Intent::CreateAccount(create_account) => create_account.sender,
+            Intent::RotateAuthKey(rotate_auth_key) => rotate_auth_key.sender,
+            Intent::AddCurrency(add_currency) => add_currency.sender,
+            Intent::Mint(mint) => mint.sender,
+            Intent::Preburn(preburn) => preburn.sender,
+            Intent::Burn(burn) => burn.sender,
+            Intent::CancelBurn(cancel_burn) => cancel_burn.sender,
+            Intent::Script(generic_script) => generic_script.sender,
+        }
+    }
+
+    /// The currency used to pay for gas
+    pub fn currency(&self) -> &str {
+        match self {
+            Intent::Transfer(transfer) => &transfer.currency,
+            Intent::CreateAccount(create_account) => &create_account.currency,
+            Intent::RotateAuthKey(rotate_auth_key) => &rotate_auth_key.gas_currency,
+            Intent::AddCurrency(add_currency) => &add_currency.gas_currency,
+            Intent::Mint(mint) => &mint.gas_currency,
+            Intent::Preburn(preburn) => &preburn.currency,
+            Intent::Burn(burn) => &burn.gas_currency,
+            Intent::CancelBurn(cancel_burn) => &cancel_burn.gas_currency,
+            Intent::Script(generic_script) => &generic_script.gas_currency,
+        }
+    }
+
+    /// Whether the intent can only be sent by the treasury compliance account
+    /// or a designated dealer
+    pub fn is_treasury_operation(&self) -> bool {
+        matches!(
+            self,
+            Intent::Mint(_) | Intent::Preburn(_) | Intent::Burn(_) | Intent::CancelBurn(_)
+        )
+    }
+
+    pub fn into_script(self) -> Result<Script, anyhow::Error> {
+        let script = match self {
+            Intent::Transfer(transfer) => stdlib::encode_peer_to_peer_with_metadata_script(
+                currency_tag(&transfer.currency)?,
+                transfer.receiver,
+                transfer.amount,
+                transfer.metadata()?,
+                transfer.metadata_signature,
+            ),
+            Intent::CreateAccount(create_account) => {
+                stdlib::encode_create_child_vasp_account_script(
+                    currency_tag(&create_account.currency)?,
+                    create_account.address,
+                    create_account.auth_key_prefix,
+                    create_account.add_all_currencies,
+                    create_account.initial_balance,
+                )
+            }
+            Intent::RotateAuthKey(rotate_auth_key) => match rotate_auth_key.sliding_nonce {
+                Some(sliding_nonce) => stdlib::encode_rotate_authentication_key_with_nonce_script(
+                    sliding_nonce,
+                    rotate_auth_key.new_key,
+                ),
+                None => stdlib::encode_rotate_authentication_key_script(rotate_auth_key.new_key),
+            },
+            Intent::AddCurrency(add_currency) => {
+                stdlib::encode_add_currency_to_account_script(currency_tag(&add_currency.currency)?)
+            }
+            Intent::Mint(mint) => stdlib::encode_tiered_mint_script(
+                currency_tag(&mint.currency)?,
+                mint.sliding_nonce,
+                mint.designated_dealer,
+                mint.amount,
+                mint.tier_index,
+            ),
+            Intent::Preburn(preburn) => {
+                stdlib::encode_preburn_script(currency_tag(&preburn.currency)?, preburn.amount)
+            }
+            Intent::Burn(burn) => stdlib::encode_burn_script(
+                currency_tag(&burn.currency)?,
+                burn.sliding_nonce,
+                burn.preburn_address,
+            ),
+            Intent::CancelBurn(cancel_burn) => stdlib::encode_cancel_burn_script(
+                currency_tag(&cancel_burn.currency)?,
+                cancel_burn.preburn_address,
+            ),
+            Intent::Script(generic_script) => generic_script.script,
+        };
+
+        Ok(script)
+    }
+
+    pub fn into_operations(self) -> Vec<Operation> {
+        match self {
+            Intent::Transfer(transfer) => vec![
+                Operation {
+                    operation_identifier: OperationIdentifier {
+                        index: 0,
+                        network_index: None,
+                    },
+                    related_operations: None,
+                    type_: "sentpayment".to_string(),
+                    status: None,
+                    account: Some(AccountIdentifier {
+                        address: (&transfer.sender).into(),
+                        sub_account: sub_account(&transfer.from_subaddress),
+                    }),
+                    amount: Some(amount(format!("-{}", transfer.amount), &transfer.currency)),
+                    metadata: transfer.attestation(),
+                },
+                Operation {
+                    operation_identifier: OperationIdentifier {
+                        index: 1,
+                        network_index: None,
+                    },
+                    related_operations: Some(vec![OperationIdentifier {
+                        index: 0,
+                        network_index: None,
+                    }]),
+                    type_: "receivedpayment".to_string(),
+                    status: None,
+                    account: Some(AccountIdentifier {
+                        address: (&transfer.receiver).into(),
+                        sub_account: sub_account(&transfer.to_subaddress),
+                    }),
+                    amount: Some(amount(format!("{}", transfer.amount), &transfer.currency)),
+                    metadata: None,
+                },
+            ],
+            Intent::CreateAccount(create_account) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "createaccount".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&create_account.address).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount(
+                    format!("{}", create_account.initial_balance),
+                    &create_account.currency,
+                )),
+                metadata: Some(OperationMetadata {
+                    sender_address: Some((&create_account.sender).into()),
+                    auth_key_prefix: Some(hex::encode(&create_account.auth_key_prefix)),
+                    add_all_currencies: Some(create_account.add_all_currencies),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::RotateAuthKey(rotate_auth_key) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "rotateauthkey".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&rotate_auth_key.sender).into(),
+                    sub_account: None,
+                }),
+                amount: None,
+                metadata: Some(OperationMetadata {
+                    new_auth_key: Some(hex::encode(&rotate_auth_key.new_key)),
+                    sliding_nonce: rotate_auth_key.sliding_nonce,
+                    currency: Some(rotate_auth_key.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::AddCurrency(add_currency) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "addcurrency".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&add_currency.sender).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount("0".to_string(), &add_currency.currency)),
+                metadata: Some(OperationMetadata {
+                    currency: Some(add_currency.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::Mint(mint) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "mint".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&mint.designated_dealer).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount(format!("{}", mint.amount), &mint.currency)),
+                metadata: Some(OperationMetadata {
+                    sender_address: Some((&mint.sender).into()),
+                    sliding_nonce: Some(mint.sliding_nonce),
+                    tier_index: Some(mint.tier_index),
+                    currency: Some(mint.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::Preburn(preburn) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "preburn".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&preburn.sender).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount(format!("-{}", preburn.amount), &preburn.currency)),
+                metadata: None,
+            }],
+            Intent::Burn(burn) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "burn".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&burn.preburn_address).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount("0".to_string(), &burn.currency)),
+                metadata: Some(OperationMetadata {
+                    sender_address: Some((&burn.sender).into()),
+                    sliding_nonce: Some(burn.sliding_nonce),
+                    currency: Some(burn.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::CancelBurn(cancel_burn) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "cancelburn".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&cancel_burn.preburn_address).into(),
+                    sub_account: None,
+                }),
+                amount: Some(amount("0".to_string(), &cancel_burn.currency)),
+                metadata: Some(OperationMetadata {
+                    sender_address: Some((&cancel_burn.sender).into()),
+                    currency: Some(cancel_burn.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+            Intent::Script(generic_script) => vec![Operation {
+                operation_identifier: OperationIdentifier {
+                    index: 0,
+                    network_index: None,
+                },
+                related_operations: None,
+                type_: "script".to_string(),
+                status: None,
+                account: Some(AccountIdentifier {
+                    address: (&generic_script.sender).into(),
+                    sub_account: None,
+                }),
+                amount: None,
+                metadata: Some(OperationMetadata {
+                    script: describe_script(&generic_script.script),
+                    currency: Some(generic_script.gas_currency),
+                    ..OperationMetadata::default()
+                }),
+            }],
+        }
+    }
+}
+
+/// Decodes the name and arguments of a stdlib script
+pub fn describe_script(script: &Script) -> Option<ScriptMetadata> {
+    // stdlib scripts are identified by their code, and named after it
+    let name = StdlibScript::try_from(script.code()).ok()?.to_string();
+
+    let type_arguments = script
+        .ty_args()
+        .iter()
+        .map(|type_tag| type_tag.to_string())
+        .collect();
+    let arguments = script
+        .args()
+        .iter()
+        .map(|argument| match argument {
+            TransactionArgument::U8(v) => v.to_string(),
+            TransactionArgument::U64(v) => v.to_string(),
+            TransactionArgument::U128(v) => v.to_string(),
+            TransactionArgument::Bool(v) => v.to_string(),
+            TransactionArgument::Address(address) => address.to_string().to_lowercase(),
+            TransactionArgument::U8Vector(bytes) => hex::encode(bytes),
+        })
+        .collect();
+
+    Some(ScriptMetadata {
+        name,
+        type_arguments,
+        arguments,
+    })
+}
+
+fn amount(value: String, currency: &str) -> Amount {
+    Amount {
+        value,
+        currency: Currency {
+            symbol: currency.to_string(),
+            decimals: 6, // TODO: use get_currencies instead of hardcoding
+        },
+    }
+}
+
+fn sub_account(subaddress: &Option<Vec<u8>>) -> Option<SubAccountIdentifier> {
+    subaddress.as_ref().map(|subaddress| SubAccountIdentifier {
+        address: hex::encode(subaddress),
+    })
+}
+
+fn subaddress(account: &AccountIdentifier) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    match &account.sub_account {
+        Some(sub_account) => {
+            let subaddress = hex::decode(&sub_account.address)?;
+            if subaddress.len() != SUBADDRESS_LENGTH {
+                return Err(anyhow!("subaddress must be {} bytes", SUBADDRESS_LENGTH));
+            }
+            Ok(Some(subaddress))
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(Default)]
+struct PaymentMetadata {
+    from_subaddress: Option<Vec<u8>>,
+    to_subaddress: Option<Vec<u8>>,
+    travel_rule_metadata: Option<String>,
+}
+
+/// Decodes the subaddresses or the travel-rule reference id from payment
+/// metadata. Other kinds of metadata are ignored.
+fn decode_payment_metadata(metadata: &[u8]) -> Result<PaymentMetadata, ApiError> {
+    if metadata.is_empty() {
+        return Ok(PaymentMetadata::default());
+    }
+
+    let metadata: Metadata =
+        bcs::from_bytes(metadata).map_err(|_| ApiError::deserialization_failed("Metadata"))?;
+    match metadata {
+        Metadata::GeneralMetadata(GeneralMetadata::GeneralMetadataVersion0(general)) => {
+            Ok(PaymentMetadata {
+                from_subaddress: general.from_subaddress,
+                to_subaddress: general.to_subaddress,
+                travel_rule_metadata: None,
+            })
+        }
+        Metadata::TravelRuleMetadata(TravelRuleMetadata::TravelRuleMetadataVersion0(
+            travel_rule,
+        )) => Ok(PaymentMetadata {
+            travel_rule_metadata: travel_rule.off_chain_reference_id,
+            ..PaymentMetadata::default()
+        }),
+        _ => Ok(PaymentMetadata::default()),
+    }
+}
+
+pub fn currency_tag(currency: &str) -> Result<TypeTag, anyhow::Error> {
+    let identifier = Identifier::new(currency)?;
+    Ok(TypeTag::Struct(StructTag {
+        address: CORE_CODE_ADDRESS,
+        module: identifier.clone(),
+        name: identifier,
+        type_params: vec![],
+    }))
+}
+
+/// Currencies are published at the core code address, in a module named after the currency
+pub fn currency_code(currency: &TypeTag) -> Result<String, ApiError> {
+    match currency {
+        TypeTag::Struct(StructTag {
+            address,
+            module,
+            name,
+            type_params,
+        }) if *address == CORE_CODE_ADDRESS && module == name && type_params.is_empty() => {
+            Ok(name.to_string())
+        }
+        _ => Err(ApiError::BadCoin),
+    }
+}
+
+fn extract_transfer_from_operations(operations: &[Operation]) -> Result<Transfer, anyhow::Error> {
+    if operations.len() != 2 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let is_p2p = match (operations[0].type_.as_ref(), operations[1].type_.as_ref()) {
+        ("sentpayment", "receivedpayment") => true,
+        ("receivedpayment", "sentpayment") => true,
+        _ => false,
+    };
+
+    if !is_p2p {
+        return Err(anyhow!("operations don't represent a transfer"));
+    }
+
+    if operations[0].account.is_none()
+        || operations[0].amount.is_none()
+        || operations[1].account.is_none()
+        || operations[1].amount.is_none()
+    {
+        return Err(anyhow!("accounts/amounts missing"));
+    }
+
+    let (send_op, recv_op) = if operations[0].type_ == "sentpayment" {
+        (&operations[0], &operations[1])
+    } else {
+        (&operations[1], &operations[0])
+    };
+    let send_account = send_op.account.as_ref().unwrap();
+    let send_amount = send_op.amount.as_ref().unwrap();
+    let recv_account = recv_op.account.as_ref().unwrap();
+    let recv_amount = recv_op.amount.as_ref().unwrap();
+
+    if send_amount.currency != recv_amount.currency {
+        return Err(anyhow!("mismatched currencies in ops"));
+    }
+
+    let send_value = send_amount.value.parse::<Value>()?;
+    let recv_value = recv_amount.value.parse::<Value>()?;
+
+    if let Value::Credit(_) = send_value {
+        return Err(anyhow!("can't send negative amounts"));
+    }
+
+    if !send_value.reconciles(recv_value) {
+        return Err(anyhow!("send and recv amounts don't net out"));
+    }
+
+    let sender = send_account.address.parse::<AccountAddress>()?;
+    let receiver = recv_account.address.parse::<AccountAddress>()?;
+    let amount = send_value.amount();
+    let currency = send_amount.currency.symbol.clone();
+    let from_subaddress = subaddress(send_account)?;
+    let to_subaddress = subaddress(recv_account)?;
+
+    let (travel_rule_metadata, metadata_signature) = match &send_op.metadata {
+        Some(metadata) => (
+            metadata.travel_rule_metadata.clone(),
+            match &metadata.metadata_signature {
+                Some(signature) => hex::decode(signature)?,
+                None => vec![],
+            },
+        ),
+        None => (None, vec![]),
+    };
+
+    Ok(Transfer {
+        sender,
+        receiver,
+        amount,
+        currency,
+        from_subaddress,
+        to_subaddress,
+        travel_rule_metadata,
+        metadata_signature,
+    })
+}
+
+fn extract_create_account_from_operations(
+    operations: &[Operation],
+) -> Result<CreateAccount, anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let operation = &operations[0];
+    let (account, metadata) = match (operation.account.as_ref(), operation.metadata.as_ref()) {
+        (Some(account), Some(metadata)) => (account, metadata),
+        _ => return Err(anyhow!("account/metadata missing")),
+    };
+
+    // without an initial balance, the currency of the new account is given
+    // as metadata
+    let (initial_balance, currency) = match &operation.amount {
+        Some(amount) => match amount.value.parse::<Value>()? {
+            Value::Credit(v) => (v, amount.currency.symbol.clone()),
+            Value::Debit(_) => return Err(anyhow!("initial balance can't be negative")),
+        },
+        None => (
+            0,
+            metadata
+                .currency
+                .clone()
+                .ok_or_else(|| anyhow!("amount/currency missing"))?,
+        ),
+    };
+
+    let sender = metadata
+        .sender_address
+        .as_ref()
+        .ok_or_else(|| anyhow!("sender address missing"))?
+        .parse::<AccountAddress>()?;
+    let auth_key_prefix = hex::decode(
+        metadata
+            .auth_key_prefix
+            .as_ref()
+            .ok_or_else(|| anyhow!("auth key prefix missing"))?,
+    )?;
+    if auth_key_prefix.len() != AUTH_KEY_PREFIX_LENGTH {
+        return Err(anyhow!(
+            "auth key prefix must be {} bytes",
+            AUTH_KEY_PREFIX_LENGTH
+        ));
+    }
+
+    let address = account.address.parse::<AccountAddress>()?;
+    let add_all_currencies = metadata.add_all_currencies.unwrap_or(false);
+
+    Ok(CreateAccount {
+        sender,
+        address,
+        auth_key_prefix,
+        add_all_currencies,
+        initial_balance,
+        currency,
+    })
+}
+
+fn extract_rotate_auth_key_from_operations(
+    operations: &[Operation],
+) -> Result<RotateAuthKey, anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let operation = &operations[0];
+    let (account, metadata) = match (operation.account.as_ref(), operation.metadata.as_ref()) {
+        (Some(account), Some(metadata)) => (account, metadata),
+        _ => return Err(anyhow!("account/metadata missing")),
+    };
+
+    let sender = account.address.parse::<AccountAddress>()?;
+    let new_key = hex::decode(
+        metadata
+            .new_auth_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("new auth key missing"))?,
+    )?;
+    if new_key.len() != AUTH_KEY_LENGTH {
+        return Err(anyhow!("auth key must be {} bytes", AUTH_KEY_LENGTH));
+    }
+
+    let gas_currency = metadata
+        .currency
+        .clone()
+        .unwrap_or_else(|| DEFAULT_GAS_CURRENCY.to_string());
+
+    Ok(RotateAuthKey {
+        sender,
+        new_key,
+        sliding_nonce: metadata.sliding_nonce,
+        gas_currency,
+    })
+}
+
+fn extract_add_currency_from_operations(
+    operations: &[Operation],
+) -> Result<AddCurrency, anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    // the currency to add is given as a zero amount, since no funds move
+    let operation = &operations[0];
+    let (account, amount) = match (operation.account.as_ref(), operation.amount.as_ref()) {
+        (Some(account), Some(amount)) => (account, amount),
+        _ => return Err(anyhow!("account/amount missing")),
+    };
+
+    if amount.value.parse::<Value>()?.amount() != 0 {
+        return Err(anyhow!("adding a currency can't move funds"));
+    }
+
+    let sender = account.address.parse::<AccountAddress>()?;
+    let currency = amount.currency.symbol.clone();
+    let gas_currency = operation
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.currency.clone())
+        .unwrap_or_else(|| DEFAULT_GAS_CURRENCY.to_string());
+
+    if currency == gas_currency {
+        return Err(anyhow!(
+            "gas must be paid in a currency the account already holds"
+        ));
+    }
+
+    Ok(AddCurrency {
+        sender,
+        currency,
+        gas_currency,
+    })
+}
+
+fn extract_mint_from_operations(operations: &[Operation]) -> Result<Mint, anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let operation = &operations[0];
+    let (account, amount, metadata) = match (
+        operation.account.as_ref(),
+        operation.amount.as_ref(),
+        operation.metadata.as_ref(),
+    ) {
+        (Some(account), Some(amount), Some(metadata)) => (account, amount, metadata),
+        _ => return Err(anyhow!("account/amount/metadata missing")),
+    };
+
+    let amount_value = match amount.value.parse::<Value>()? {
+        Value::Credit(v) => v,
+        Value::Debit(_) => return Err(anyhow!("can't mint negative amounts")),
+    };
+
+    let sender = metadata
+        .sender_address
+        .as_ref()
+        .ok_or_else(|| anyhow!("sender address missing"))?
+        .parse::<AccountAddress>()?;
+    let sliding_nonce = metadata
+        .sliding_nonce
+        .ok_or_else(|| anyhow!("sliding nonce missing"))?;
+    let tier_index = metadata
+        .tier_index
+        .ok_or_else(|| anyhow!("tier index missing"))?;
+
+    let designated_dealer = account.address.parse::<AccountAddress>()?;
+    let gas_currency = metadata
+        .currency
+        .clone()
+        .unwrap_or_else(|| DEFAULT_GAS_CURRENCY.to_string());
+
+    Ok(Mint {
+        sender,
+        designated_dealer,
+        amount: amount_value,
+        currency: amount.currency.symbol.clone(),
+        sliding_nonce,
+        tier_index,
+        gas_currency,
+    })
+}
+
+fn extract_preburn_from_operations(operations: &[Operation]) -> Result<Preburn, anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let operation = &operations[0];
+    let (account, amount) = match (operation.account.as_ref(), operation.amount.as_ref()) {
+        (Some(account), Some(amount)) => (account, amount),
+        _ => return Err(anyhow!("account/amount missing")),
+    };
+
+    let amount_value = match amount.value.parse::<Value>()? {
+        Value::Debit(v) => v,
+        Value::Credit(_) => return Err(anyhow!("preburn must debit the account")),
+    };
+
+    Ok(Preburn {
+        sender: account.address.parse::<AccountAddress>()?,
+        amount: amount_value,
+        currency: amount.currency.symbol.clone(),
+    })
+}
+
+/// Burns and cancelled burns act on a whole preburn request, so the currency
+/// is given as a zero amount on the preburn account
+fn extract_preburn_request(
+    operations: &[Operation],
+) -> Result<(AccountAddress, AccountAddress, String, &OperationMetadata), anyhow::Error> {
+    if operations.len() != 1 {
+        return Err(anyhow!("wrong number of ops"));
+    }
+
+    let operation = &operations[0];
+    let (account, amount, metadata) = match (
+        operation.account.as_ref(),
+        operation.amount.as_ref(),
+        operation.metadata.as_ref(),
+    ) {
+        (Some(account), Some(amount), Some(metadata)) => (account, amount, metadata),
+        _ => return Err(anyhow!("account/amount/metadata missing")),
+    };
+
+    if amount.value.parse::<Value>()?.amount() != 0 {
+        return Err(anyhow!("the burned amount is set by the preburn request"));
+    }
+
+    let sender = metadata
+        .sender_address
+        .as_ref()
+        .ok_or_else(|| anyhow!("sender address missing"))?
+        .parse::<AccountAddress>()?;
+    let preburn_address = account.address.parse::<AccountAddress>()?;
+
+    Ok((
+        sender,
+        preburn_address,
+        amount.currency.symbol.clone(),
+        metadata,
+    ))
+}
+
+fn extract_burn_from_operations(operations: &[Operation]) -> Result<Burn, anyhow::Error> {
+    let (sender, preburn_address, currency, metadata) = extract_preburn_request(operations)?;
+    let sliding_nonce = metadata
+        .sliding_nonce
+        .ok_or_else(|| anyhow!("sliding nonce missing"))?;
+    let gas_currency = metadata
+        .currency
+        .clone()
+        .unwrap_or_else(|| DEFAULT_GAS_CURRENCY.to_string());
+
+    Ok(Burn {
+        sender,
+        preburn_address,
+        currency,
+        sliding_nonce,
+        gas_currency,
+    })
+}
+
+fn extract_cancel_burn_from_operations(
+    operations: &[Operation],
+) -> Result<CancelBurn, anyhow::Error> {
+    let (sender, preburn_address, currency, metadata) = extract_preburn_request(operations)?;
+    let gas_currency = metadata
+        .currency
+        .clone()
+        .unwrap_or_else(|| DEFAULT_GAS_CURRENCY.to_string());
+
+    Ok(CancelBurn {
+        sender,
+        preburn_address,
+        currency,
+        gas_currency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SENDER: &str = "000000000000000000000000000000dd";
+    const RECEIVER: &str = "0000000000000000000000000000abcd";
+
+    fn currency(symbol: &str) -> serde_json::Value {
+        json!({ "symbol": symbol, "decimals": 6 })
+    }
+
+    /// Builds the script of the operations, and decodes it back into
+    /// operations, as /construction/payloads and /construction/parse do
+    fn round_trip(operations: serde_json::Value) {
+        let operations: Vec<Operation> = serde_json::from_value(operations).unwrap();
+        let intent = Intent::from_operations(&operations).unwrap();
+        let sender = intent.sender();
+        let gas_currency = intent.currency().to_string();
+        let script = intent.into_script().unwrap();
+
+        let decoded = Intent::from_script(sender, &gas_currency, &script)
+            .unwrap()
+            .into_operations();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&operations).unwrap()
+        );
+    }
+
+    #[test]
+    fn create_account_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "createaccount",
+            "account": { "address": RECEIVER },
+            "amount": { "value": "1000", "currency": currency("XUS") },
+            "metadata": {
+                "sender_address": SENDER,
+                "auth_key_prefix": hex::encode(&[3; AUTH_KEY_PREFIX_LENGTH]),
+                "add_all_currencies": true,
+            },
+        }]));
+    }
+
+    #[test]
+    fn create_account_without_initial_balance() {
+        let operations: Vec<Operation> = serde_json::from_value(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "createaccount",
+            "account": { "address": RECEIVER },
+            "metadata": {
+                "sender_address": SENDER,
+                "auth_key_prefix": hex::encode(&[3; AUTH_KEY_PREFIX_LENGTH]),
+                "currency": "XUS",
+            },
+        }]))
+        .unwrap();
+
+        match Intent::from_operations(&operations).unwrap() {
+            Intent::CreateAccount(create_account) => {
+                assert_eq!(create_account.initial_balance, 0);
+                assert_eq!(create_account.currency, "XUS");
+                assert!(!create_account.add_all_currencies);
+            }
+            _ => panic!("expected a createaccount intent"),
+        }
+    }
+
+    fn transfer(
+        sender_sub_account: Option<&str>,
+        receiver_sub_account: Option<&str>,
+        metadata: Option<serde_json::Value>,
+    ) -> serde_json::Value {
+        let sub_account = |address: Option<&str>| address.map(|a| json!({ "address": a }));
+        json!([
+            {
+                "operation_identifier": { "index": 0 },
+                "type": "sentpayment",
+                "account": { "address": SENDER, "sub_account": sub_account(sender_sub_account) },
+                "amount": { "value": "-1000", "currency": currency("XUS") },
+                "metadata": metadata,
+            },
+            {
+                "operation_identifier": { "index": 1 },
+                "related_operations": [{ "index": 0 }],
+                "type": "receivedpayment",
+                "account": { "address": RECEIVER, "sub_account": sub_account(receiver_sub_account) },
+                "amount": { "value": "1000", "currency": currency("XUS") },
+            },
+        ])
+    }
+
+    #[test]
+    fn transfer_round_trip() {
+        round_trip(transfer(None, None, None));
+    }
+
+    #[test]
+    fn transfer_with_subaddresses_round_trip() {
+        round_trip(transfer(
+            Some("0102030405060708"),
+            Some("1112131415161718"),
+            None,
+        ));
+        round_trip(transfer(None, Some("1112131415161718"), None));
+    }
+
+    #[test]
+    fn transfer_with_travel_rule_metadata_round_trip() {
+        round_trip(transfer(
+            None,
+            None,
+            Some(json!({
+                "travel_rule_metadata": "off-chain-reference-id",
+                "metadata_signature": hex::encode(&[7; 64]),
+            })),
+        ));
+    }
+
+    #[test]
+    fn travel_rule_metadata_cannot_carry_subaddresses() {
+        let operations: Vec<Operation> = serde_json::from_value(transfer(
+            Some("0102030405060708"),
+            None,
+            Some(json!({ "travel_rule_metadata": "off-chain-reference-id" })),
+        ))
+        .unwrap();
+        let intent = Intent::from_operations(&operations).unwrap();
+        assert!(intent.into_script().is_err());
+    }
+
+    #[test]
+    fn rotate_auth_key_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "rotateauthkey",
+            "account": { "address": SENDER },
+            "metadata": {
+                "new_auth_key": hex::encode(&[4; AUTH_KEY_LENGTH]),
+                "currency": "XUS",
+            },
+        }]));
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "rotateauthkey",
+            "account": { "address": SENDER },
+            "metadata": {
+                "new_auth_key": hex::encode(&[4; AUTH_KEY_LENGTH]),
+                "sliding_nonce": 12,
+                "currency": "XUS",
+            },
+        }]));
+    }
+
+    #[test]
+    fn add_currency_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "addcurrency",
+            "account": { "address": SENDER },
+            "amount": { "value": "0", "currency": currency("XDX") },
+            "metadata": { "currency": "XUS" },
+        }]));
+    }
+
+    #[test]
+    fn other_scripts_are_described() {
+        let sender = SENDER.parse::<AccountAddress>().unwrap();
+        let receiver = RECEIVER.parse::<AccountAddress>().unwrap();
+        let script = stdlib::encode_freeze_account_script(12, receiver);
+
+        let operations = Intent::from_script(sender, "XUS", &script)
+            .unwrap()
+            .into_operations();
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].type_, "script");
+
+        let metadata = operations[0].metadata.as_ref().unwrap();
+        let described = metadata.script.as_ref().unwrap();
+        assert_eq!(
+            described.arguments,
+            vec!["12".to_string(), RECEIVER.to_string()]
+        );
+    }
+
+    #[test]
+    fn mint_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "mint",
+            "account": { "address": RECEIVER },
+            "amount": { "value": "1000", "currency": currency("XUS") },
+            "metadata": {
+                "sender_address": SENDER,
+                "sliding_nonce": 12,
+                "tier_index": 1,
+                "currency": "XUS",
+            },
+        }]));
+    }
+
+    #[test]
+    fn preburn_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "preburn",
+            "account": { "address": SENDER },
+            "amount": { "value": "-1000", "currency": currency("XUS") },
+        }]));
+    }
+
+    #[test]
+    fn burn_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "burn",
+            "account": { "address": RECEIVER },
+            "amount": { "value": "0", "currency": currency("XUS") },
+            "metadata": {
+                "sender_address": SENDER,
+                "sliding_nonce": 12,
+                "currency": "XUS",
+            },
+        }]));
+    }
+
+    #[test]
+    fn cancel_burn_round_trip() {
+        round_trip(json!([{
+            "operation_identifier": { "index": 0 },
+            "type": "cancelburn",
+            "account": { "address": RECEIVER },
+            "amount": { "value": "0", "currency": currency("XUS") },
+            "metadata": {
+                "sender_address": SENDER,
+                "currency": "XUS",
+            },
+        }]));
+    }
+}
@@ -1,11 +1,134 @@
+use diem_types::chain_id::ChainId;
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 use url::Url;
 
 #[derive(Clone, Debug, StructOpt)]
 pub struct Options {
-    #[structopt(long, parse(try_from_str = Url::parse))]
-    pub diem_endpoint: Url,
+    /// Fullnode JSON-RPC endpoints, in order of preference. Can be given
+    /// several times to fail over between fullnodes.
+    #[structopt(long, required = true, parse(try_from_str = Url::parse))]
+    pub diem_endpoint: Vec<Url>,
 
     #[structopt(long)]
     pub network: String,
+
+    /// Maximum number of requests in flight to the Diem endpoints
+    #[structopt(long, default_value = "64", parse(try_from_str = parse_nonzero))]
+    pub max_concurrent_requests: usize,
+
+    /// Maximum number of idle connections kept open to a Diem endpoint
+    #[structopt(long, default_value = "32")]
+    pub max_idle_connections: usize,
+
+    /// How long an idle connection to a Diem endpoint is kept open
+    #[structopt(long, default_value = "90")]
+    pub idle_connection_timeout_secs: u64,
+
+    /// Interval of TCP keep-alive probes on connections to a Diem endpoint
+    #[structopt(long, default_value = "60")]
+    pub tcp_keepalive_secs: u64,
+
+    /// How many times a failed read is retried, on the healthiest endpoint
+    #[structopt(long, default_value = "3")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled on every retry and jittered
+    #[structopt(long, default_value = "100")]
+    pub retry_base_delay_ms: u64,
+
+    /// Upper bound on the delay between retries
+    #[structopt(long, default_value = "2000")]
+    pub retry_max_delay_ms: u64,
+
+    /// Consecutive failures after which an endpoint is taken out of rotation
+    #[structopt(long, default_value = "5")]
+    pub circuit_breaker_threshold: u32,
+
+    /// How long an endpoint stays out of rotation before it is tried again
+    #[structopt(long, default_value = "30")]
+    pub circuit_breaker_cooldown_secs: u64,
+
+    /// Number of endpoints that version-pinned reads are sent to and compared
+    /// across, to detect a fullnode serving inconsistent data. Cross-checking
+    /// is disabled when this is 1.
+    #[structopt(long, default_value = "1")]
+    pub cross_check_endpoints: usize,
+
+    /// How long a cross-checked read waits for each of the other endpoints,
+    /// which are skipped when they don't answer in time
+    #[structopt(long, default_value = "2000")]
+    pub cross_check_timeout_ms: u64,
+
+    /// Number of blocks kept in memory. Caching is disabled when this is 0.
+    #[structopt(long, default_value = "10000")]
+    pub block_cache_size: usize,
+
+    /// Upper bound on the memory used by cached blocks
+    #[structopt(long, default_value = "268435456")]
+    pub block_cache_max_bytes: usize,
+
+    /// Directory of the on-disk block store. Blocks are only cached in
+    /// memory when this isn't set.
+    #[structopt(long, parse(from_os_str))]
+    pub block_store_path: Option<PathBuf>,
+
+    /// Upper bound on the size of the blocks in the on-disk store
+    #[structopt(long, default_value = "10737418240")]
+    pub block_store_max_bytes: u64,
+
+    /// How many blocks ahead of sequential /block requests are prefetched.
+    /// Prefetching is disabled when this is 0.
+    #[structopt(long, default_value = "1000")]
+    pub prefetch_window: u64,
+
+    /// Number of blocks fetched by each prefetch request
+    #[structopt(long, default_value = "100", parse(try_from_str = parse_nonzero))]
+    pub prefetch_batch_size: u64,
+
+    /// Maximum number of prefetch requests in flight
+    #[structopt(long, default_value = "4", parse(try_from_str = parse_nonzero))]
+    pub prefetch_concurrency: usize,
+
+    /// How long /construction/submit waits for execution, when asked to
+    #[structopt(long, default_value = "30")]
+    pub submit_wait_timeout_secs: u64,
+
+    /// Allow constructing mint, preburn, burn and cancelburn transactions
+    #[structopt(long)]
+    pub enable_treasury_operations: bool,
+
+    /// Port to serve Prometheus metrics on, at /metrics. Metrics aren't
+    /// served when unset.
+    #[structopt(long)]
+    pub metrics_port: Option<u16>,
+
+    /// How far the ledger timestamp may lag behind the clock before /readyz
+    /// reports the proxy as not ready
+    #[structopt(long, default_value = "60")]
+    pub max_ledger_lag_secs: u64,
+}
+
+impl Options {
+    /// The chain id of the network, if it is named after a chain (like
+    /// `testnet`) or given as a number
+    pub fn chain_id(&self) -> Option<ChainId> {
+        if self.network.is_empty() {
+            return None;
+        }
+        ChainId::from_str(&self.network).ok()
+    }
+}
+
+/// Parses a count that has to be at least 1
+fn parse_nonzero<T>(s: &str) -> Result<T, String>
+where
+    T: FromStr + Default + PartialEq,
+    T::Err: Display,
+{
+    let value = s.parse::<T>().map_err(|e| e.to_string())?;
+    if value == T::default() {
+        return Err("must be greater than 0".to_string());
+    }
+    Ok(value)
 }
@@ -0,0 +1,299 @@
+use crate::types::TransactionIdentifierMetadata;
+use move_core_types::account_address::AccountAddress;
+use once_cell::sync::Lazy;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+/// How long a submission is remembered after it expires, so that late
+/// retries still get the original outcome
+const SUBMISSION_RETENTION_SECS: u64 = 600;
+
+/// Upper bound on the number of remembered submissions
+const MAX_SUBMISSIONS: usize = 10_000;
+
+/// Transactions submitted through the proxy that may still be in mempool
+static PENDING: Lazy<Mutex<PendingTransactions>> =
+    Lazy::new(|| Mutex::new(PendingTransactions::default()));
+
+#[derive(Clone, Debug)]
+pub struct Submission {
+    pub sender: AccountAddress,
+    pub sequence_number: u64,
+    pub expiration_secs: u64,
+    /// Known once the transaction is no longer pending
+    pub outcome: Option<TransactionIdentifierMetadata>,
+}
+
+/// A sequence number used by a submitted transaction
+#[derive(Clone, Debug)]
+struct Slot {
+    expiration_secs: u64,
+    hash: String,
+}
+
+#[derive(Default)]
+struct PendingTransactions {
+    /// Sequence numbers of pending transactions, by sender
+    accounts: HashMap<AccountAddress, BTreeMap<u64, Slot>>,
+    /// Submitted transactions by hash
+    submissions: HashMap<String, Submission>,
+}
+
+impl PendingTransactions {
+    /// Records a submission, unless one with the same hash is already
+    /// recorded, which is returned instead
+    fn insert(&mut self, hash: &str, submission: Submission) -> Option<Submission> {
+        if let Some(existing) = self.submissions.get(hash) {
+            return Some(existing.clone());
+        }
+
+        self.accounts.entry(submission.sender).or_default().insert(
+            submission.sequence_number,
+            Slot {
+                expiration_secs: submission.expiration_secs,
+                hash: hash.to_string(),
+            },
+        );
+        self.submissions.insert(hash.to_string(), submission);
+        None
+    }
+
+    /// Forgets a submission, and frees its sequence number unless another
+    /// transaction was submitted with it since
+    fn remove(&mut self, hash: &str) {
+        let submission = match self.submissions.remove(hash) {
+            Some(submission) => submission,
+            None => return,
+        };
+
+        if let Some(pending) = self.accounts.get_mut(&submission.sender) {
+            let is_same = pending
+                .get(&submission.sequence_number)
+                .map_or(false, |slot| slot.hash == hash);
+            if is_same {
+                pending.remove(&submission.sequence_number);
+            }
+            if pending.is_empty() {
+                self.accounts.remove(&submission.sender);
+            }
+        }
+    }
+
+    /// Drops the transactions that can no longer be executed because the
+    /// on-chain sequence number moved past them
+    fn reconcile(&mut self, sender: &AccountAddress, chain_sequence_number: u64) {
+        if let Some(pending) = self.accounts.get_mut(sender) {
+            *pending = pending.split_off(&chain_sequence_number);
+            if pending.is_empty() {
+                self.accounts.remove(sender);
+            }
+        }
+    }
+
+    fn next_sequence_number(&self, sender: &AccountAddress, chain_sequence_number: u64) -> u64 {
+        let mut sequence_number = chain_sequence_number;
+        if let Some(pending) = self.accounts.get(sender) {
+            while pending.contains_key(&sequence_number) {
+                sequence_number += 1;
+            }
+        }
+        sequence_number
+    }
+
+    /// Drops the transactions that expired, of every sender
+    fn prune_accounts(&mut self, now_secs: u64) {
+        self.accounts.retain(|_, pending| {
+            pending.retain(|_, slot| slot.expiration_secs > now_secs);
+            !pending.is_empty()
+        });
+    }
+
+    /// Forgets submissions past their retention, and the ones closest to
+    /// expiring when there are too many
+    fn prune_submissions(&mut self, now_secs: u64) {
+        self.submissions.retain(|_, submission| {
+            submission.expiration_secs + SUBMISSION_RETENTION_SECS > now_secs
+        });
+
+        if self.submissions.len() > MAX_SUBMISSIONS {
+            let mut by_expiration: Vec<_> = self
+                .submissions
+                .iter()
+                .map(|(hash, submission)| (submission.expiration_secs, hash.clone()))
+                .collect();
+            by_expiration.sort();
+
+            let excess = self.submissions.len() - MAX_SUBMISSIONS;
+            for (_, hash) in by_expiration.into_iter().take(excess) {
+                self.submissions.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Records a transaction about to be submitted, so that its sequence number
+/// stays in use until it executes or expires, and so that retries get its
+/// outcome once it is known. If the transaction is already recorded, the
+/// earlier submission is returned instead.
+pub fn record_submission(
+    hash: &str,
+    sender: AccountAddress,
+    sequence_number: u64,
+    expiration_secs: u64,
+    now_secs: u64,
+) -> Option<Submission> {
+    let mut pending = PENDING.lock().unwrap();
+    pending.prune_accounts(now_secs);
+    pending.prune_submissions(now_secs);
+    pending.insert(
+        hash,
+        Submission {
+            sender,
+            sequence_number,
+            expiration_secs,
+            outcome: None,
+        },
+    )
+}
+
+/// Forgets a submission the node rejected, and frees its sequence number
+pub fn forget_submission(hash: &str) {
+    PENDING.lock().unwrap().remove(hash);
+}
+
+pub fn record_outcome(hash: &str, outcome: TransactionIdentifierMetadata) {
+    let mut pending = PENDING.lock().unwrap();
+    if let Some(submission) = pending.submissions.get_mut(hash) {
+        submission.outcome = Some(outcome);
+    }
+}
+
+pub fn find_submission(hash: &str) -> Option<Submission> {
+    let pending = PENDING.lock().unwrap();
+    pending.submissions.get(hash).cloned()
+}
+
+/// The first sequence number at or after the on-chain one that isn't used by
+/// a pending transaction
+pub fn next_sequence_number(
+    sender: &AccountAddress,
+    chain_sequence_number: u64,
+    now_secs: u64,
+) -> u64 {
+    let mut pending = PENDING.lock().unwrap();
+    pending.prune_accounts(now_secs);
+    pending.reconcile(sender, chain_sequence_number);
+    pending.next_sequence_number(sender, chain_sequence_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000;
+    const EXPIRATION: u64 = NOW + 10;
+
+    fn sender() -> AccountAddress {
+        AccountAddress::new([1; AccountAddress::LENGTH])
+    }
+
+    fn submission(sequence_number: u64) -> Submission {
+        Submission {
+            sender: sender(),
+            sequence_number,
+            expiration_secs: EXPIRATION,
+            outcome: None,
+        }
+    }
+
+    #[test]
+    fn skips_pending_sequence_numbers() {
+        let mut pending = PendingTransactions::default();
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 5);
+
+        pending.insert("a", submission(5));
+        pending.insert("b", submission(6));
+        pending.insert("c", submission(8));
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 7);
+    }
+
+    #[test]
+    fn reconcile_drops_executed_sequence_numbers() {
+        let mut pending = PendingTransactions::default();
+        pending.insert("a", submission(5));
+        pending.insert("b", submission(6));
+
+        pending.reconcile(&sender(), 6);
+        assert_eq!(pending.next_sequence_number(&sender(), 6), 7);
+        pending.reconcile(&sender(), 7);
+        assert!(pending.accounts.is_empty());
+    }
+
+    #[test]
+    fn prune_drops_expired_sequence_numbers() {
+        let mut pending = PendingTransactions::default();
+        pending.insert("a", submission(5));
+
+        pending.prune_accounts(EXPIRATION - 1);
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 6);
+        pending.prune_accounts(EXPIRATION);
+        assert!(pending.accounts.is_empty());
+    }
+
+    #[test]
+    fn duplicate_submission_returns_the_first_one() {
+        let mut pending = PendingTransactions::default();
+        assert!(pending.insert("a", submission(5)).is_none());
+
+        let existing = pending.insert("a", submission(6)).unwrap();
+        assert_eq!(existing.sequence_number, 5);
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 6);
+    }
+
+    #[test]
+    fn removed_submission_frees_its_sequence_number() {
+        let mut pending = PendingTransactions::default();
+        pending.insert("a", submission(5));
+
+        pending.remove("a");
+        assert!(pending.submissions.is_empty());
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 5);
+    }
+
+    #[test]
+    fn removal_keeps_a_replacing_submission() {
+        let mut pending = PendingTransactions::default();
+        pending.insert("a", submission(5));
+        pending.insert("b", submission(5));
+
+        pending.remove("a");
+        assert_eq!(pending.next_sequence_number(&sender(), 5), 6);
+    }
+
+    #[test]
+    fn prune_forgets_submissions_past_retention() {
+        let mut pending = PendingTransactions::default();
+        pending.insert("a", submission(5));
+
+        pending.prune_submissions(EXPIRATION + SUBMISSION_RETENTION_SECS - 1);
+        assert!(pending.submissions.contains_key("a"));
+        pending.prune_submissions(EXPIRATION + SUBMISSION_RETENTION_SECS);
+        assert!(pending.submissions.is_empty());
+    }
+
+    #[test]
+    fn prune_forgets_the_closest_to_expiring_when_full() {
+        let mut pending = PendingTransactions::default();
+        for i in 0..=MAX_SUBMISSIONS as u64 {
+            let mut submission = submission(i);
+            submission.expiration_secs += i;
+            pending.insert(&i.to_string(), submission);
+        }
+
+        pending.prune_submissions(NOW);
+        assert_eq!(pending.submissions.len(), MAX_SUBMISSIONS);
+        assert!(!pending.submissions.contains_key("0"));
+    }
+}
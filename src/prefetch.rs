@@ -0,0 +1,413 @@
+use crate::{
+    block, cache::BlockCache, diem::Diem, error::ApiError, options::Options, store::BlockStore,
+    types::BlockResponse,
+};
+use diem_json_rpc_client::views::TransactionDataView;
+use log::debug;
+use once_cell::sync::Lazy;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Requests this close to the last one of a run count as sequential, since sync
+/// fetches several blocks concurrently and they arrive slightly out of order
+const SEQUENTIAL_DISTANCE: u64 = 64;
+
+/// Number of sequential requests before prefetching starts
+const SEQUENTIAL_THRESHOLD: u64 = 8;
+
+/// Number of sequential runs tracked at once, so that several clients
+/// syncing at different heights each get prefetching
+const MAX_RUNS: usize = 8;
+
+/// How long prefetching pauses after a failed batch, doubling with each
+/// consecutive failure up to `MAX_FAILURE_BACKOFF`
+const FAILURE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_FAILURE_BACKOFF: Duration = Duration::from_secs(60);
+
+static PREFETCHER: Lazy<Mutex<Prefetcher>> = Lazy::new(|| Mutex::new(Prefetcher::default()));
+
+#[derive(Default)]
+struct Prefetcher {
+    /// Sequential runs of requests, the most recently requested last
+    runs: Vec<Run>,
+    /// Number of batches being fetched, across runs
+    in_flight: usize,
+    /// Number of batches that failed in a row
+    consecutive_failures: u32,
+    /// Set after a failed batch, and nothing is prefetched until then
+    paused_until: Option<Instant>,
+}
+
+/// A run of sequential requests
+struct Run {
+    /// Highest version requested in the run
+    last_version: u64,
+    /// Number of requests in the run
+    streak: u64,
+    /// Versions below this are already prefetched, or being prefetched
+    prefetched_until: u64,
+}
+
+impl Run {
+    fn is_sequential(&self, version: u64) -> bool {
+        version.saturating_add(SEQUENTIAL_DISTANCE) > self.last_version
+            && version <= self.last_version.saturating_add(SEQUENTIAL_DISTANCE)
+    }
+}
+
+impl Prefetcher {
+    /// Tracks a request, and returns the batches to prefetch as (start, limit).
+    /// Nothing past `ledger_version`, the latest version known, is prefetched.
+    fn on_request(
+        &mut self,
+        version: u64,
+        ledger_version: u64,
+        options: &Options,
+        now: Instant,
+    ) -> Vec<(u64, u64)> {
+        let position = self.runs.iter().position(|run| run.is_sequential(version));
+        let mut run = match position {
+            Some(position) => {
+                let mut run = self.runs.remove(position);
+                run.streak += 1;
+                run.last_version = run.last_version.max(version);
+                run
+            }
+            None => {
+                // the least recently requested run is dropped to make room
+                if self.runs.len() >= MAX_RUNS {
+                    self.runs.remove(0);
+                }
+                Run {
+                    last_version: version,
+                    streak: 0,
+                    prefetched_until: 0,
+                }
+            }
+        };
+
+        let is_paused = self
+            .paused_until
+            .map_or(false, |paused_until| paused_until > now);
+
+        let mut batches = vec![];
+        if run.streak >= SEQUENTIAL_THRESHOLD && !is_paused {
+            // the genesis block has no parent to fetch along with it, so it is
+            // never prefetched
+            let window_end = version
+                .saturating_add(1)
+                .saturating_add(options.prefetch_window)
+                .min(ledger_version.saturating_add(1));
+            run.prefetched_until = run.prefetched_until.max(version.saturating_add(1)).max(1);
+
+            while self.in_flight < options.prefetch_concurrency && run.prefetched_until < window_end
+            {
+                let start = run.prefetched_until;
+                let limit = options.prefetch_batch_size.min(window_end - start);
+                run.prefetched_until += limit;
+                self.in_flight += 1;
+                batches.push((start, limit));
+            }
+        }
+
+        self.runs.push(run);
+        batches
+    }
+
+    fn on_batch_done(&mut self, start: u64, result: &Result<(), ApiError>, now: Instant) {
+        self.in_flight -= 1;
+        if result.is_ok() {
+            self.consecutive_failures = 0;
+            return;
+        }
+
+        // the batch is fetched again if its run goes on, once the pause passes
+        for run in &mut self.runs {
+            if run.last_version < start && start < run.prefetched_until {
+                run.prefetched_until = start;
+            }
+        }
+        let backoff = FAILURE_BACKOFF
+            .checked_mul(1 << self.consecutive_failures.min(16))
+            .map_or(MAX_FAILURE_BACKOFF, |backoff| {
+                backoff.min(MAX_FAILURE_BACKOFF)
+            });
+        self.consecutive_failures += 1;
+        self.paused_until = Some(now + backoff);
+    }
+}
+
+/// Notices sequential /block requests, and fetches the blocks ahead of them
+/// into the cache in the background. Blocks are only prefetched up to the
+/// latest version the proxy has seen, which /network/status keeps up to date.
+pub fn on_block_request(
+    version: u64,
+    options: &Options,
+    diem: &Diem,
+    cache: &Arc<BlockCache>,
+    store: &Option<Arc<BlockStore>>,
+) {
+    if options.prefetch_window == 0 || options.block_cache_size == 0 {
+        return;
+    }
+
+    let batches = PREFETCHER.lock().unwrap().on_request(
+        version,
+        diem.latest_version(),
+        options,
+        Instant::now(),
+    );
+    for (start, limit) in batches {
+        let diem = diem.clone();
+        let cache = cache.clone();
+        let store = store.clone();
+        tokio::spawn(async move {
+            let result = prefetch(&diem, &cache, store.as_deref(), start, limit).await;
+            if let Err(e) = &result {
+                debug!("failed to prefetch {} blocks from {}: {}", limit, start, e);
+            }
+            PREFETCHER
+                .lock()
+                .unwrap()
+                .on_batch_done(start, &result, Instant::now());
+        });
+    }
+}
+
+/// Prefetches blocks into the cache, which requests for them wait for
+async fn prefetch(
+    diem: &Diem,
+    cache: &BlockCache,
+    store: Option<&BlockStore>,
+    start: u64,
+    limit: u64,
+) -> Result<(), ApiError> {
+    cache
+        .fetch_range(start..start + limit, || {
+            fetch_blocks(diem, store, start, limit)
+        })
+        .await
+}
+
+/// Fetches the blocks from `start` in one batch, along with the parent of the
+/// first one. Starting from the ledger timestamp at `start`, the timestamp of
+/// later blocks is updated by the block metadata transactions that set it.
+async fn fetch_blocks(
+    diem: &Diem,
+    store: Option<&BlockStore>,
+    start: u64,
+    limit: u64,
+) -> Result<Vec<(u64, BlockResponse)>, ApiError> {
+    let (metadata, transactions) = diem
+        .get_metadata_with_transactions(Some(start), start - 1, limit + 1, true)
+        .await?;
+
+    let mut blocks = vec![];
+    let mut timestamp_usecs = metadata.timestamp;
+    for pair in transactions.windows(2) {
+        let (parent, tx) = (&pair[0], &pair[1]);
+        if let TransactionDataView::BlockMetadata {
+            timestamp_usecs: block_timestamp,
+        } = &tx.transaction
+        {
+            timestamp_usecs = *block_timestamp;
+        }
+
+        let block = block::convert_block(timestamp_usecs, parent, tx);
+        if let Some(store) = store {
+            store.insert(tx.version, &block);
+        }
+        blocks.push((tx.version, block));
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    const LEDGER_VERSION: u64 = 1_000_000;
+
+    fn options(args: &[&str]) -> Options {
+        let required = [
+            "proxy",
+            "--diem-endpoint",
+            "http://localhost:8080",
+            "--network",
+            "testnet",
+        ];
+        Options::from_iter(required.iter().chain(args))
+    }
+
+    /// Requests `count` sequential versions from `start`, and returns the
+    /// batches of the last request
+    fn request_run(
+        prefetcher: &mut Prefetcher,
+        options: &Options,
+        start: u64,
+        count: u64,
+        now: Instant,
+    ) -> Vec<(u64, u64)> {
+        let mut batches = vec![];
+        for version in start..start + count {
+            batches = prefetcher.on_request(version, LEDGER_VERSION, options, now);
+        }
+        batches
+    }
+
+    #[test]
+    fn prefetches_after_enough_sequential_requests() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+
+        assert!(request_run(&mut prefetcher, &options, 100, SEQUENTIAL_THRESHOLD, now).is_empty());
+        assert_eq!(
+            prefetcher.on_request(100 + SEQUENTIAL_THRESHOLD, LEDGER_VERSION, &options, now),
+            vec![(109, 100), (209, 100), (309, 100), (409, 100)]
+        );
+        assert_eq!(prefetcher.in_flight, options.prefetch_concurrency);
+    }
+
+    #[test]
+    fn prefetches_more_as_batches_complete() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+        request_run(
+            &mut prefetcher,
+            &options,
+            100,
+            SEQUENTIAL_THRESHOLD + 1,
+            now,
+        );
+
+        assert!(prefetcher
+            .on_request(109, LEDGER_VERSION, &options, now)
+            .is_empty());
+        prefetcher.on_batch_done(109, &Ok(()), now);
+        assert_eq!(
+            prefetcher.on_request(110, LEDGER_VERSION, &options, now),
+            vec![(509, 100)]
+        );
+    }
+
+    #[test]
+    fn failed_batch_is_prefetched_again_after_a_pause() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+        request_run(
+            &mut prefetcher,
+            &options,
+            100,
+            SEQUENTIAL_THRESHOLD + 1,
+            now,
+        );
+
+        prefetcher.on_batch_done(109, &Ok(()), now);
+        prefetcher.on_batch_done(209, &Err(ApiError::BadBlockRequest), now);
+        assert!(prefetcher
+            .on_request(109, LEDGER_VERSION, &options, now)
+            .is_empty());
+
+        let later = now + FAILURE_BACKOFF;
+        assert_eq!(
+            prefetcher.on_request(110, LEDGER_VERSION, &options, later),
+            vec![(209, 100), (309, 100)]
+        );
+    }
+
+    #[test]
+    fn pauses_longer_after_consecutive_failures() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+        request_run(
+            &mut prefetcher,
+            &options,
+            100,
+            SEQUENTIAL_THRESHOLD + 1,
+            now,
+        );
+
+        prefetcher.on_batch_done(409, &Err(ApiError::BadBlockRequest), now);
+        prefetcher.on_batch_done(309, &Err(ApiError::BadBlockRequest), now);
+        assert_eq!(prefetcher.paused_until, Some(now + 2 * FAILURE_BACKOFF));
+
+        prefetcher.on_batch_done(209, &Ok(()), now);
+        assert_eq!(prefetcher.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn stops_at_the_ledger_version() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+        for version in 100..100 + SEQUENTIAL_THRESHOLD {
+            prefetcher.on_request(version, 250, &options, now);
+        }
+
+        assert_eq!(
+            prefetcher.on_request(108, 250, &options, now),
+            vec![(109, 100), (209, 42)]
+        );
+        assert!(prefetcher.on_request(109, 250, &options, now).is_empty());
+    }
+
+    #[test]
+    fn versions_near_the_end_of_the_range_dont_overflow() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+
+        let start = u64::MAX - SEQUENTIAL_THRESHOLD - 1;
+        assert!(request_run(
+            &mut prefetcher,
+            &options,
+            start,
+            SEQUENTIAL_THRESHOLD + 1,
+            now
+        )
+        .is_empty());
+        assert!(prefetcher
+            .on_request(u64::MAX, u64::MAX, &options, now)
+            .is_empty());
+    }
+
+    #[test]
+    fn prefetches_for_several_runs() {
+        let options = options(&["--prefetch-window", "200", "--prefetch-concurrency", "8"]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+
+        let mut batches = vec![];
+        for i in 0..=SEQUENTIAL_THRESHOLD {
+            batches.extend(prefetcher.on_request(100 + i, LEDGER_VERSION, &options, now));
+            batches.extend(prefetcher.on_request(100_000 + i, LEDGER_VERSION, &options, now));
+        }
+        assert_eq!(
+            batches,
+            vec![(109, 100), (209, 100), (100_009, 100), (100_109, 100)]
+        );
+    }
+
+    #[test]
+    fn least_recent_run_is_dropped() {
+        let options = options(&[]);
+        let mut prefetcher = Prefetcher::default();
+        let now = Instant::now();
+        request_run(&mut prefetcher, &options, 100, SEQUENTIAL_THRESHOLD, now);
+
+        for i in 1..=MAX_RUNS as u64 {
+            prefetcher.on_request(i * 100_000, LEDGER_VERSION, &options, now);
+        }
+        assert!(prefetcher
+            .on_request(108, LEDGER_VERSION, &options, now)
+            .is_empty());
+    }
+}
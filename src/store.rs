@@ -0,0 +1,182 @@
+use crate::types::BlockResponse;
+use log::warn;
+use std::{convert::TryInto, path::Path};
+
+/// Length of the checksum stored in front of every block
+const CHECKSUM_LENGTH: usize = 4;
+
+/// Key of the total size of the stored blocks, in the default tree
+const BYTES_KEY: &[u8] = b"bytes";
+
+/// Converted blocks persisted on disk, so that they survive restarts. Blocks
+/// are keyed by version, and indexed by hash.
+pub struct BlockStore {
+    db: sled::Db,
+    blocks: sled::Tree,
+    hashes: sled::Tree,
+    max_bytes: u64,
+}
+
+impl BlockStore {
+    /// Opens the store in the given directory
+    pub fn open(path: &Path, max_bytes: u64) -> Result<BlockStore, anyhow::Error> {
+        let db = sled::open(path)?;
+        let blocks = db.open_tree("blocks")?;
+        let hashes = db.open_tree("hashes")?;
+
+        // the total size is kept along with the blocks, so that opening a
+        // large store doesn't scan it
+        if db.get(BYTES_KEY)?.is_none() {
+            let mut bytes = 0;
+            for entry in blocks.iter() {
+                let (_, value) = entry?;
+                bytes += value.len() as u64;
+            }
+            db.insert(BYTES_KEY, &bytes.to_be_bytes()[..])?;
+        }
+
+        Ok(BlockStore {
+            db,
+            blocks,
+            hashes,
+            max_bytes,
+        })
+    }
+
+    pub fn get(&self, version: u64) -> Option<BlockResponse> {
+        let value = match self.blocks.get(version.to_be_bytes()) {
+            Ok(value) => value?,
+            Err(e) => {
+                warn!("failed to read block {} from the store: {}", version, e);
+                return None;
+            }
+        };
+
+        // a corrupted block is dropped, so that it gets fetched again
+        match decode(&value) {
+            Some(block) => Some(block),
+            None => {
+                warn!("block {} is corrupted in the store, dropping it", version);
+                self.remove(version);
+                None
+            }
+        }
+    }
+
+    pub fn get_by_hash(&self, hash: &str) -> Option<BlockResponse> {
+        let version = self.hashes.get(hash.as_bytes()).ok()??;
+        let version = u64::from_be_bytes(version.as_ref().try_into().ok()?);
+
+        // the index isn't updated atomically with the blocks, so it may be stale
+        self.get(version)
+            .filter(|block| block.block.block_identifier.hash == hash)
+    }
+
+    pub fn insert(&self, version: u64, block: &BlockResponse) {
+        let value = match encode(block) {
+            Some(value) => value,
+            None => return,
+        };
+        let size = value.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+
+        let hash = block.block.block_identifier.hash.as_bytes();
+        let replaced = match self.blocks.insert(version.to_be_bytes(), value) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                warn!("failed to write block {} to the store: {}", version, e);
+                return;
+            }
+        };
+        if let Err(e) = self.hashes.insert(hash, &version.to_be_bytes()[..]) {
+            warn!("failed to index block {} in the store: {}", version, e);
+        }
+
+        let replaced_size = replaced.map_or(0, |replaced| replaced.len() as u64);
+        self.update_bytes(size, replaced_size);
+
+        self.evict();
+    }
+
+    /// Total size of the stored blocks
+    fn bytes(&self) -> u64 {
+        match self.db.get(BYTES_KEY) {
+            Ok(bytes) => bytes.as_ref().map_or(0, |bytes| decode_u64(bytes)),
+            Err(e) => {
+                warn!("failed to read the size of the store: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Updates the total size of the stored blocks, atomically with respect
+    /// to other updates
+    fn update_bytes(&self, added: u64, removed: u64) {
+        let result = self.db.update_and_fetch(BYTES_KEY, |bytes| {
+            let bytes = (bytes.map_or(0, decode_u64) + added).saturating_sub(removed);
+            Some(bytes.to_be_bytes().to_vec())
+        });
+        if let Err(e) = result {
+            warn!("failed to update the size of the store: {}", e);
+        }
+    }
+
+    /// Drops the lowest versions until the store fits its size cap, since
+    /// sync moves towards the latest version and rarely reads old blocks again
+    fn evict(&self) {
+        while self.bytes() > self.max_bytes {
+            let (key, value) = match self.blocks.pop_min() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("failed to evict blocks from the store: {}", e);
+                    break;
+                }
+            };
+            self.update_bytes(0, value.len() as u64);
+            self.remove_hash(&key, &value);
+        }
+    }
+
+    fn remove(&self, version: u64) {
+        if let Ok(Some(value)) = self.blocks.remove(version.to_be_bytes()) {
+            self.update_bytes(0, value.len() as u64);
+            self.remove_hash(&version.to_be_bytes(), &value);
+        }
+    }
+
+    fn remove_hash(&self, key: &[u8], value: &[u8]) {
+        if let Some(block) = decode(value) {
+            let hash = block.block.block_identifier.hash;
+            // only if the hash still points to this version
+            let _ = self
+                .hashes
+                .compare_and_swap(hash.as_bytes(), Some(key), None as Option<&[u8]>);
+        }
+    }
+}
+
+fn decode_u64(bytes: &[u8]) -> u64 {
+    bytes.try_into().map_or(0, u64::from_be_bytes)
+}
+
+/// Blocks are stored as JSON, behind a CRC32 checksum of the JSON bytes
+fn encode(block: &BlockResponse) -> Option<Vec<u8>> {
+    let json = serde_json::to_vec(block).ok()?;
+    let mut value = crc32fast::hash(&json).to_be_bytes().to_vec();
+    value.extend_from_slice(&json);
+    Some(value)
+}
+
+fn decode(value: &[u8]) -> Option<BlockResponse> {
+    if value.len() < CHECKSUM_LENGTH {
+        return None;
+    }
+    let (checksum, json) = value.split_at(CHECKSUM_LENGTH);
+    if checksum != &crc32fast::hash(json).to_be_bytes()[..] {
+        return None;
+    }
+    serde_json::from_slice(json).ok()
+}
@@ -75,6 +75,8 @@ pub struct Operation {
     pub account: Option<AccountIdentifier>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub amount: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<OperationMetadata>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -103,6 +105,8 @@ pub struct Signature {
 pub struct Transaction {
     pub transaction_identifier: TransactionIdentifier,
     pub operations: Vec<Operation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TransactionMetadata>,
 }
 
 // Identifiers
@@ -219,6 +223,7 @@ pub struct ConstructionDeriveRequest {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ConstructionDeriveResponse {
     pub account_identifier: AccountIdentifier,
+    pub metadata: DeriveMetadata,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -231,6 +236,9 @@ pub struct ConstructionHashRequest {
 pub struct ConstructionMetadataRequest {
     pub network_identifier: NetworkIdentifier,
     pub options: MetadataOptions,
+    /// The keys that will sign, which must match the on-chain authentication key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_keys: Option<Vec<PublicKey>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -249,6 +257,7 @@ pub struct ConstructionParseRequest {
 pub struct ConstructionParseResponse {
     pub operations: Vec<Operation>,
     pub account_identifier_signers: Vec<AccountIdentifier>,
+    pub metadata: ParseMetadata,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -268,6 +277,8 @@ pub struct ConstructionPayloadsResponse {
 pub struct ConstructionPreprocessRequest {
     pub network_identifier: NetworkIdentifier,
     pub operations: Vec<Operation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<PreprocessMetadata>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -279,6 +290,8 @@ pub struct ConstructionPreprocessResponse {
 pub struct ConstructionSubmitRequest {
     pub network_identifier: NetworkIdentifier,
     pub signed_transaction: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SubmitMetadata>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -337,6 +350,8 @@ pub struct NetworkStatusResponse {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TransactionIdentifierResponse {
     pub transaction_identifier: TransactionIdentifier,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TransactionIdentifierMetadata>,
 }
 
 // Miscellaneous
@@ -385,6 +400,119 @@ pub struct Version {
 pub struct MetadataOptions {
     /// The account that will construct the transaction
     pub sender_address: String,
+    /// The receiver of a payment, which must exist and hold the currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receiver_address: Option<String>,
+    /// The amount of a payment, used to check the sender balance and for dual attestation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u64>,
+    /// The currency of a payment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Use the on-chain sequence number, ignoring transactions pending in mempool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_chain_sequence_number: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SubmitMetadata {
+    /// Wait for the transaction to execute before responding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wait_for_execution: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransactionIdentifierMetadata {
+    /// Either `pending`, `committed`, or `replaced` by another transaction
+    /// with the same sequence number
+    pub status: String,
+    /// Whether the transaction had already been submitted through the proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<bool>,
+    /// The version the transaction was committed at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeriveMetadata {
+    /// Hex-encoded authentication key of the public key
+    pub auth_key: String,
+    /// Hex-encoded authentication key prefix, needed to create the account
+    pub auth_key_prefix: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ParseMetadata {
+    pub sequence_number: u64,
+    pub expiration_timestamp_secs: u64,
+    pub max_gas_amount: u64,
+    pub gas_unit_price: u64,
+    pub gas_currency: String,
+    pub chain_id: u8,
+    /// The name of the stdlib script
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PreprocessMetadata {
+    /// Use the on-chain sequence number, ignoring transactions pending in mempool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_chain_sequence_number: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OperationMetadata {
+    /// The account that sends the transaction, when it isn't the operation account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_address: Option<String>,
+    /// Hex-encoded authentication key prefix of a new account
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_key_prefix: Option<String>,
+    /// Whether a new account should hold a balance in every currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_all_currencies: Option<bool>,
+    /// Off-chain reference id of a travel-rule exchange
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub travel_rule_metadata: Option<String>,
+    /// Hex-encoded compliance signature for dual attestation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_signature: Option<String>,
+    /// Hex-encoded authentication key an account is rotated to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_auth_key: Option<String>,
+    /// Sliding nonce authorizing a privileged transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sliding_nonce: Option<u64>,
+    /// Tier of a designated dealer a mint is checked against
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tier_index: Option<u64>,
+    /// The currency of an operation without an amount, or the gas currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The decoded script of a generic script operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptMetadata>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScriptMetadata {
+    /// The name of the stdlib script
+    pub name: String,
+    pub type_arguments: Vec<String>,
+    pub arguments: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransactionMetadata {
+    /// The decoded script of a user transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<ScriptMetadata>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -397,4 +525,16 @@ pub struct ErrorDetails {
 pub struct ConstructionMetadata {
     pub chain_id: u8,
     pub sequence_number: u64,
+    /// The on-chain travel-rule threshold, in micro-XDX
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dual_attestation_limit: Option<u64>,
+    /// Whether the payment amount requires a compliance signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dual_attestation_required: Option<bool>,
+    /// Off-chain reference id of a travel-rule exchange
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub travel_rule_metadata: Option<String>,
+    /// Hex-encoded compliance signature for dual attestation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_signature: Option<String>,
 }